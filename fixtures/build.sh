@@ -0,0 +1,8 @@
+#!/bin/sh
+# Regenerates the object files used by the unit tests, needs GNU as
+set -e
+cd "$(dirname "$0")"
+sed 's/ADDRESS/.quad/' functions.s | as --64 -o functions-x86_64.o
+sed 's/ADDRESS/.long/' functions.s | as --32 -o functions-i386.o
+sed 's/ADDRESS/.quad/' malformed.s | as --64 -o malformed-x86_64.o
+sed 's/ADDRESS/.long/' malformed.s | as --32 -o malformed-i386.o
@@ -0,0 +1,46 @@
+# Relocatable object with `-Z function-sections` style sections and `.stack_sizes` linked to them
+#
+# Built by build.sh as `functions-x86_64.o` (`.rela.stack_sizes`) and `functions-i386.o` (`.rel.stack_sizes`).
+
+    .section .text.first,"ax",@progbits
+    .globl first
+    .type first,@function
+first:
+    nop; nop; nop; nop
+    .size first, 4
+
+    # local and without a size, reaches up to `after`
+    .type zero,@function
+zero:
+    nop; nop
+
+    .globl after
+    .type after,@function
+after:
+    ret
+    .size after, 1
+
+    .section .text.other,"ax",@progbits
+    # claims more than its section holds
+    .globl huge
+    .type huge,@function
+huge:
+    ret; ret
+    .size huge, 100
+
+    .section .text.last,"ax",@progbits
+    # without a size, reaches up to the end of the section
+    .globl tail
+    .type tail,@function
+tail:
+    nop; nop; ret
+
+    .section .stack_sizes,"o",@progbits,.text.first,unique,1
+    ADDRESS first
+    .uleb128 16
+    ADDRESS zero
+    .uleb128 8
+
+    .section .stack_sizes,"o",@progbits,.text.other,unique,2
+    ADDRESS huge
+    .uleb128 48
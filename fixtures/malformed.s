@@ -0,0 +1,44 @@
+# Relocatable object with corrupt `.stack_sizes` records
+#
+# Built by build.sh as `malformed-x86_64.o` and `malformed-i386.o`, with stack sizes right at and one byte over
+# `MAX_FRAME` (1 GiB).
+
+    .section .text.first,"ax",@progbits
+    .globl first
+    .type first,@function
+first:
+    ret
+    .size first, 1
+
+    .globl second
+    .type second,@function
+second:
+    ret
+    .size second, 1
+
+    .section .text.third,"ax",@progbits
+    .globl third
+    .type third,@function
+third:
+    ret
+    .size third, 1
+
+    .globl fourth
+    .type fourth,@function
+fourth:
+    ret
+    .size fourth, 1
+
+    .section .stack_sizes,"o",@progbits,.text.first,unique,1
+    ADDRESS first
+    .uleb128 0x40000001
+    # still decoded after the oversized record
+    ADDRESS second
+    .uleb128 24
+
+    .section .stack_sizes,"o",@progbits,.text.third,unique,2
+    ADDRESS fourth
+    .uleb128 0x40000000
+    ADDRESS third
+    # overlong, eleven bytes encode more than 64 bits
+    .byte 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00
@@ -0,0 +1,72 @@
+use xmas_elf::{header::Machine, ElfFile};
+
+const EM_XTENSA: u16 = 94;
+const EM_RISCV: u16 = 243;
+
+/// What the `.stack_sizes` numbers of a target include
+///
+/// LLVM records the size of the function's own frame: locals, spilled registers and the area for arguments
+/// passed on the stack to its callees. Arguments the function receives on the stack live in its caller's
+/// frame. Where the call instruction pushes the return address, that slot isn't included either.
+pub struct Abi {
+    arch: &'static str,
+    /// Bytes the call instruction pushes, added by `--abi-normalize`
+    pub return_address: u64,
+    /// Alignment of the stack pointer at calls the ABI requires, zero if unknown
+    pub alignment: u64,
+}
+
+impl Abi {
+    pub fn of(elf: &ElfFile) -> Abi {
+        let (arch, return_address, alignment) = match elf.header.pt2.machine().as_machine() {
+            Machine::X86 => ("x86", 4, 16),
+            Machine::X86_64 => ("x86_64", 8, 16),
+            // AAPCS
+            Machine::Arm => ("ARM", 0, 8),
+            Machine::AArch64 => ("AArch64", 0, 16),
+            Machine::Other(EM_RISCV) => ("RISC-V", 0, 16),
+            Machine::Other(EM_XTENSA) => ("Xtensa", 0, 16),
+            _ => ("unknown architecture", 0, 0),
+        };
+        Abi {
+            arch,
+            return_address,
+            alignment,
+        }
+    }
+
+    /// Rounds a frame up so the stack pointer stays aligned at the calls the function makes
+    ///
+    /// The return address pushed by the caller counts towards the alignment. Functions without a frame
+    /// don't call anything and stay at zero, leaf functions with a frame can't be told apart from the others
+    /// and are rounded up too. That's why `--align` is opt-in.
+    pub fn align(&self, stack: u64) -> u64 {
+        if stack == 0 || self.alignment == 0 {
+            return stack;
+        }
+        (stack + self.return_address).next_multiple_of(self.alignment) - self.return_address
+    }
+
+    /// Names the stack alignment `align` rounds to, `None` for unknown architectures
+    pub fn alignment_note(&self) -> Option<String> {
+        (self.alignment > 0).then(|| {
+            format!(
+                "Stack sizes ({}): frames rounded up to the {} byte stack alignment (--align), leaf functions might need less",
+                self.arch, self.alignment
+            )
+        })
+    }
+
+    /// Describes what the shown stack sizes include
+    pub fn note(&self, normalize: bool) -> String {
+        let arch = self.arch;
+        let size = self.return_address;
+        if size == 0 {
+            format!("Stack sizes ({arch}): own frame and outgoing arguments, incoming stack arguments belong to the caller")
+        } else if normalize {
+            format!("Stack sizes ({arch}): own frame, outgoing arguments and the {size} byte return address pushed by the caller (--abi-normalize)")
+        } else {
+            format!("Stack sizes ({arch}): own frame and outgoing arguments, without the {size} byte return address pushed by the caller")
+        }
+    }
+}
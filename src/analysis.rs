@@ -0,0 +1,1067 @@
+//! Parsing of the stack usage information ([`.stack_sizes`]) emitted by LLVM
+//!
+//! Based on the executable analysis of the [`stack-sizes`](https://crates.io/crates/stack-sizes) crate
+//!
+//! The crate itself isn't used: it panics (`unreachable!()`) on `.stack_sizes` entries no symbol matches,
+//! so the unmatched entries couldn't be counted or reported, and it doesn't expose the decoded records.
+//!
+//! [`.stack_sizes`]: https://llvm.org/docs/CodeGenerator.html#emitting-function-stack-size-information
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Cursor,
+};
+
+use anyhow::bail;
+use byteorder::{ReadBytesExt, LE};
+use xmas_elf::{
+    header,
+    sections::{SectionData, ShType, SHF_ALLOC, SHF_EXECINSTR},
+    symbol_table::{Entry, Type},
+    ElfFile,
+};
+
+use crate::{cfi, dwarf, map::MapSymbol};
+
+/// Functions found after analyzing an executable
+#[derive(Clone, Debug)]
+pub struct Functions<'a> {
+    /// Whether the addresses of these functions are 32-bit or 64-bit
+    pub have_32_bit_addresses: bool,
+
+    /// "undefined" symbols, symbols that need to be dynamically loaded
+    pub undefined: HashSet<&'a str>,
+
+    /// "defined" symbols, symbols with known locations (addresses)
+    ///
+    /// Keyed and iterated by address, the order is the same on every run.
+    pub defined: BTreeMap<u64, Function<'a>>,
+
+    /// Number of `.stack_sizes` entries attributed to a function
+    pub matched: usize,
+
+    /// `.stack_sizes` entries whose address didn't match any function
+    pub unmatched: Vec<(u64, u64)>,
+
+    /// Every decoded `.stack_sizes` entry in the order of the file
+    pub entries: Vec<StackSizesEntry<'a>>,
+
+    /// Malformed `.stack_sizes` records that were skipped
+    pub malformed: Vec<String>,
+}
+
+/// Largest stack frame taken as real by default, `.stack_sizes` records with larger ones are taken for corrupt
+/// metadata and skipped
+///
+/// No thread gets a GiB of stack, Linux starts the main thread with 8 MiB.
+pub const MAX_FRAME: u64 = 1 << 30;
+
+/// Orders `(size, address, name)` by size, largest first, ties go by address and then by name
+///
+/// Functions of the same size end up in the same order on every run.
+pub fn largest_first<N: Ord>(a: (u64, u64, N), b: (u64, u64, N)) -> Ordering {
+    b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2))
+}
+
+impl<'a> Functions<'a> {
+    /// Returns the defined functions and their addresses by stack size, largest first
+    ///
+    /// Functions without a stack size count as zero, ties are ordered by [`largest_first`].
+    pub fn by_stack_desc(&self) -> Vec<(u64, &Function<'a>)> {
+        self.sorted_by(|f| f.stack.unwrap_or(0))
+    }
+
+    /// Returns the defined functions and their addresses by code size, largest first
+    ///
+    /// Ties are ordered by [`largest_first`].
+    pub fn by_code_desc(&self) -> Vec<(u64, &Function<'a>)> {
+        self.sorted_by(|f| f.size)
+    }
+
+    fn sorted_by(&self, size: impl Fn(&Function) -> u64) -> Vec<(u64, &Function<'a>)> {
+        let mut functions: Vec<_> = self.defined.iter().map(|(&a, f)| (a, f)).collect();
+        functions.sort_by(|a, b| {
+            largest_first((size(a.1), a.0, a.1.names()), (size(b.1), b.0, b.1.names()))
+        });
+        functions
+    }
+
+    /// Fills in the stack size of the functions without a `.stack_sizes` entry with the frame size estimated
+    /// from the call frame information, returns the number of estimates
+    pub fn estimate_from_cfi(&mut self, elf: &[u8], thumb_fixup: bool) -> anyhow::Result<usize> {
+        let elf = &ElfFile::new(elf).map_err(anyhow::Error::msg)?;
+        let mut estimated = 0;
+        for (address, size) in cfi::frame_sizes(elf) {
+            if let Some(function) = function_at(&mut self.defined, address, thumb_fixup) {
+                if function.stack.is_none() {
+                    function.stack = Some(size);
+                    function.estimated = true;
+                    estimated += 1;
+                }
+            }
+        }
+        Ok(estimated)
+    }
+
+    /// Names the functions whose symbols are only local labels after the subprograms of the debug information,
+    /// and adds the functions of the unmatched `.stack_sizes` entries it describes, returns the number of
+    /// functions named
+    pub fn names_from_debug(&mut self, elf: &'a [u8], thumb_fixup: bool) -> anyhow::Result<usize> {
+        let elf = ElfFile::new(elf).map_err(anyhow::Error::msg)?;
+        let mut subprograms = HashMap::new();
+        for subprogram in dwarf::subprograms(&elf) {
+            subprograms.entry(subprogram.address).or_insert(subprogram);
+        }
+        let lookup = |address: u64| {
+            subprograms.get(&address).or_else(|| {
+                thumb_fixup
+                    .then(|| subprograms.get(&(address ^ 1)))
+                    .flatten()
+            })
+        };
+
+        let mut named = 0;
+        for (&address, function) in &mut self.defined {
+            if function.names.iter().all(|name| is_label(name)) {
+                if let Some(subprogram) = lookup(address) {
+                    function.names = vec![subprogram.name];
+                    named += 1;
+                }
+            }
+        }
+
+        for (address, stack) in std::mem::take(&mut self.unmatched) {
+            let Some(subprogram) = lookup(address) else {
+                self.unmatched.push((address, stack));
+                continue;
+            };
+            let size = subprogram.size.unwrap_or(0);
+            self.defined.insert(
+                address,
+                Function {
+                    names: vec![subprogram.name],
+                    size,
+                    symbol_size: size,
+                    extent: None,
+                    stack: Some(stack),
+                    estimated: false,
+                },
+            );
+            self.matched += 1;
+            named += 1;
+        }
+        for entry in self.entries.iter_mut().filter(|e| e.function.is_none()) {
+            entry.function = self
+                .defined
+                .get(&entry.address)
+                .and_then(|f| f.names.first().copied());
+        }
+        Ok(named)
+    }
+}
+
+/// A record of a `.stack_sizes` section as decoded, before and regardless of matching it to a function
+#[derive(Clone, Debug)]
+pub struct StackSizesEntry<'a> {
+    /// Index of the `.stack_sizes` section, relocatable objects have several
+    pub section: usize,
+    /// Byte offset of the record within the section
+    pub offset: u64,
+    /// Address after applying relocations
+    pub address: u64,
+    pub stack: u64,
+    /// Mangled name of the function the entry was attributed to
+    pub function: Option<&'a str>,
+}
+
+/// A symbol that represents a function (subroutine)
+#[derive(Clone, Debug)]
+pub struct Function<'a> {
+    names: Vec<&'a str>,
+    size: u64,
+    /// Size as given by the symbol table or the map file, before `compute_extents` fills in zero sizes
+    symbol_size: u64,
+    extent: Option<u64>,
+    stack: Option<u64>,
+    /// Whether `stack` is estimated from the call frame information rather than read from `.stack_sizes`
+    estimated: bool,
+}
+
+impl<'a> Function<'a> {
+    /// Returns the (mangled) name of the function and its aliases
+    pub fn names(&self) -> &[&'a str] {
+        &self.names
+    }
+
+    /// Returns the size of this subroutine in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the size of the symbol, zero for many naked and assembly functions unlike `size`
+    pub fn symbol_size(&self) -> u64 {
+        self.symbol_size
+    }
+
+    /// Returns the distance to the next function or the end of the containing section in bytes
+    ///
+    /// `None` if the function isn't located in an executable section
+    pub fn extent(&self) -> Option<u64> {
+        self.extent
+    }
+
+    /// Returns the stack usage of the function in bytes
+    pub fn stack(&self) -> Option<u64> {
+        self.stack
+    }
+
+    /// Returns whether the stack usage is an estimate made by [`Functions::estimate_from_cfi`]
+    pub fn estimated(&self) -> bool {
+        self.estimated
+    }
+}
+
+impl<'a> Function<'a> {
+    /// A function of `size` bytes known by `names` that extends as far as its size
+    pub fn new(names: Vec<&'a str>, size: u64, stack: Option<u64>) -> Function<'a> {
+        Function {
+            names,
+            size,
+            symbol_size: size,
+            extent: Some(size),
+            stack,
+            estimated: false,
+        }
+    }
+}
+
+/// Whether the name of a function symbol says nothing about the function, like the local labels (`.L...`) of
+/// heavily optimized code
+fn is_label(name: &str) -> bool {
+    name.is_empty() || name.starts_with(".L") || is_tag(name)
+}
+
+// is this symbol a tag used to delimit code / data sections within a subroutine?
+fn is_tag(name: &str) -> bool {
+    name == "$a" || name == "$t" || name == "$d" || {
+        (name.starts_with("$a.") || name.starts_with("$d.") || name.starts_with("$t."))
+            && name.split_once('.').unwrap().1.parse::<u64>().is_ok()
+    }
+}
+
+/// Looks up the function at `address`, with `thumb_fixup` trying with the thumb bit both set and clear
+fn function_at<'f, 'a>(
+    defined: &'f mut BTreeMap<u64, Function<'a>>,
+    address: u64,
+    thumb_fixup: bool,
+) -> Option<&'f mut Function<'a>> {
+    if !thumb_fixup {
+        defined.get_mut(&address)
+    } else if defined.contains_key(&(address | 1)) {
+        defined.get_mut(&(address | 1))
+    } else {
+        defined.get_mut(&(address & !1))
+    }
+}
+
+#[derive(Default)]
+struct Symbols<'a> {
+    undefined: HashSet<&'a str>,
+    defined: BTreeMap<u64, Function<'a>>,
+    /// Address of every symbol table entry, indexed like the symbol table
+    addresses: Vec<u64>,
+}
+
+/// Returns the address each section is placed at
+///
+/// Sections of relocatable objects all start at zero, so they get laid out one after another to give
+/// every function a distinct address.
+fn section_bases(elf: &ElfFile) -> Vec<u64> {
+    let relocatable = elf.header.pt2.type_().as_type() == header::Type::Relocatable;
+
+    let mut next = 0;
+    elf.section_iter()
+        .map(|section| {
+            if !relocatable {
+                section.address()
+            } else if section.flags() & SHF_ALLOC != 0 {
+                let base = next;
+                next = (next + section.size() + 15) & !15;
+                base
+            } else {
+                0
+            }
+        })
+        .collect()
+}
+
+/// Names and address ranges of the executable sections, with the same addresses the functions get
+pub fn code_sections(elf: &[u8]) -> anyhow::Result<Vec<(String, u64, u64)>> {
+    let elf = &ElfFile::new(elf).map_err(anyhow::Error::msg)?;
+    Ok(elf
+        .section_iter()
+        .zip(section_bases(elf))
+        .filter(|(s, _)| s.flags() & (SHF_ALLOC | SHF_EXECINSTR) == SHF_ALLOC | SHF_EXECINSTR)
+        .map(|(s, base)| {
+            let name = s.get_name(elf).unwrap_or("?").to_string();
+            (name, base, base + s.size())
+        })
+        .collect())
+}
+
+/// Number of sections occupying memory at runtime, code as well as data
+pub fn allocatable_sections(elf: &[u8]) -> anyhow::Result<usize> {
+    let elf = &ElfFile::new(elf).map_err(anyhow::Error::msg)?;
+    Ok(elf
+        .section_iter()
+        .filter(|s| s.flags() & SHF_ALLOC != 0)
+        .count())
+}
+
+/// Describes where the program starts: the entry point, the function there and the vector table of firmware
+/// (`.vector_table`, as placed by `cortex-m-rt`)
+///
+/// `None` for relocatable objects and other ELFs without an entry point.
+pub fn startup(elf: &ElfFile) -> Option<String> {
+    let entry = elf.header.pt2.entry_point();
+    if entry == 0 {
+        return None;
+    }
+
+    let function = match elf
+        .find_section_by_name(".symtab")
+        .and_then(|section| section.get_data(elf).ok())
+    {
+        Some(SectionData::SymbolTable32(entries)) => function_named_at(entries, elf, entry),
+        Some(SectionData::SymbolTable64(entries)) => function_named_at(entries, elf, entry),
+        _ => None,
+    };
+    let mut note = format!("Entry point: {entry:#x}");
+    if let Some(name) = function {
+        note.push_str(&format!(" `{:#}`", rustc_demangle::demangle(name)));
+    }
+    if let Some(vector_table) = elf.find_section_by_name(".vector_table") {
+        note.push_str(&format!(", vector table at {:#x}", vector_table.address()));
+    }
+    Some(note)
+}
+
+/// Name of the function symbol at `address`, ignoring the thumb bit
+fn function_named_at<'a, E: Entry>(
+    entries: &'a [E],
+    elf: &ElfFile<'a>,
+    address: u64,
+) -> Option<&'a str> {
+    entries
+        .iter()
+        .filter(|e| e.get_type() == Ok(Type::Func) && e.value() & !1 == address & !1)
+        .find_map(|e| e.get_name(elf).ok())
+}
+
+/// Name of the section in `code_sections` containing `address`, ignoring the thumb bit
+pub fn section_of(sections: &[(String, u64, u64)], address: u64) -> Option<&str> {
+    let address = address & !1;
+    sections
+        .iter()
+        .find(|(_, start, end)| (*start..*end).contains(&address))
+        .map(|(name, ..)| name.as_str())
+}
+
+fn process_symtab_exec<'a, E>(
+    entries: &'a [E],
+    elf: &ElfFile<'a>,
+    bases: &[u64],
+    thumb_fixup: bool,
+) -> anyhow::Result<Symbols<'a>>
+where
+    E: Entry,
+{
+    let relocatable = elf.header.pt2.type_().as_type() == header::Type::Relocatable;
+
+    let mut defined = BTreeMap::new();
+    let mut maybe_aliases = BTreeMap::new();
+    let mut undefined = HashSet::new();
+    let mut addresses = Vec::with_capacity(entries.len());
+
+    for entry in entries {
+        let ty = entry.get_type();
+        let size = entry.size();
+        let name = entry.get_name(elf);
+        // symbol values of relocatable objects are offsets into their section
+        let mut value = entry.value();
+        if relocatable {
+            value += bases.get(usize::from(entry.shndx())).copied().unwrap_or(0);
+        }
+        addresses.push(value);
+
+        if ty == Ok(Type::Func) {
+            let name = name.map_err(anyhow::Error::msg)?;
+
+            if entry.shndx() == 0 || (value == 0 && size == 0) {
+                undefined.insert(name);
+            } else {
+                defined
+                    .entry(value)
+                    .or_insert(Function {
+                        names: vec![],
+                        size,
+                        symbol_size: size,
+                        extent: None,
+                        stack: None,
+                        estimated: false,
+                    })
+                    .names
+                    .push(name);
+            }
+        } else if ty == Ok(Type::NoType) && entry.shndx() != 0 {
+            if let Ok(name) = name {
+                if !is_tag(name) {
+                    maybe_aliases.entry(value).or_insert(vec![]).push(name);
+                }
+            }
+        }
+    }
+
+    for (value, alias) in maybe_aliases {
+        if let Some(sym) = function_at(&mut defined, value, thumb_fixup) {
+            sym.names.extend(alias);
+        }
+    }
+
+    // the symbol table order differs between toolchain versions
+    for function in defined.values_mut() {
+        function.names.sort_unstable();
+        function.names.dedup();
+    }
+
+    Ok(Symbols {
+        undefined,
+        defined,
+        addresses,
+    })
+}
+
+/// Computes the extent of every function and uses it as the code size if the symbol's size is zero or
+/// exceeds the containing section
+///
+/// The extent reaches up to the next function in the same section or the end of the section.
+/// With `-Z function-sections` the bounds of the section are the best indication of where a function ends.
+fn compute_extents(elf: &ElfFile, bases: &[u64], defined: &mut BTreeMap<u64, Function>) {
+    let text = code_ranges(elf, bases);
+
+    let starts: Vec<u64> = defined.keys().map(|address| address & !1).collect();
+    for (i, (address, function)) in defined.iter_mut().enumerate() {
+        let start = address & !1;
+        let Some(&(_, section_end)) = text.iter().find(|(lo, hi)| (*lo..*hi).contains(&start))
+        else {
+            continue;
+        };
+
+        let end = starts
+            .get(i + 1)
+            .map(|&next| next.min(section_end))
+            .unwrap_or(section_end);
+        let extent = end.saturating_sub(start);
+        function.extent = Some(extent);
+
+        if function.size == 0 || start + function.size > section_end {
+            function.size = extent;
+        }
+    }
+}
+
+/// Address ranges of the executable sections
+fn code_ranges(elf: &ElfFile, bases: &[u64]) -> Vec<(u64, u64)> {
+    elf.section_iter()
+        .zip(bases)
+        .filter(|(s, _)| s.flags() & (SHF_ALLOC | SHF_EXECINSTR) == SHF_ALLOC | SHF_EXECINSTR)
+        .map(|(s, &base)| (base, base + s.size()))
+        .collect()
+}
+
+/// Collects the relocations applying to the section at `index`, keyed by the offset into that section
+///
+/// The value is the index of the referenced symbol and the explicit addend (`None` for `SHT_REL`)
+fn relocations(
+    elf: &ElfFile,
+    index: usize,
+    section_address: u64,
+) -> anyhow::Result<HashMap<u64, (usize, Option<u64>)>> {
+    let relocatable = elf.header.pt2.type_().as_type() == header::Type::Relocatable;
+    // offsets are virtual addresses in anything but relocatable objects
+    let offset = |r_offset: u64| {
+        if relocatable {
+            r_offset
+        } else {
+            r_offset.wrapping_sub(section_address)
+        }
+    };
+
+    let mut relocations = HashMap::new();
+    for section in elf.section_iter() {
+        if !matches!(section.get_type(), Ok(ShType::Rela | ShType::Rel))
+            || section.info() as usize != index
+        {
+            continue;
+        }
+
+        match section.get_data(elf).map_err(anyhow::Error::msg)? {
+            SectionData::Rela32(entries) => {
+                for r in entries {
+                    let addend = r.get_addend() as i32 as i64 as u64;
+                    relocations.insert(
+                        offset(u64::from(r.get_offset())),
+                        (r.get_symbol_table_index() as usize, Some(addend)),
+                    );
+                }
+            }
+            SectionData::Rela64(entries) => {
+                for r in entries {
+                    relocations.insert(
+                        offset(r.get_offset()),
+                        (r.get_symbol_table_index() as usize, Some(r.get_addend())),
+                    );
+                }
+            }
+            // the addend of `SHT_REL` is stored in place and already final unless this is an object file
+            SectionData::Rel32(entries) if relocatable => {
+                for r in entries {
+                    relocations.insert(
+                        u64::from(r.get_offset()),
+                        (r.get_symbol_table_index() as usize, None),
+                    );
+                }
+            }
+            SectionData::Rel64(entries) if relocatable => {
+                for r in entries {
+                    relocations.insert(r.get_offset(), (r.get_symbol_table_index() as usize, None));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(relocations)
+}
+
+/// Rejects files that can't be an ELF file, like the stub an interrupted build leaves behind
+///
+/// Gives a clearer error than the ELF parser does for these.
+pub fn check_plausible(elf: &[u8]) -> anyhow::Result<()> {
+    // size of the ELF header of 32-bit files
+    const MIN_SIZE: usize = 52;
+
+    if elf.is_empty() {
+        bail!("The file is empty, it's probably left over from a failed build - rebuild it or point --out-override at the right file");
+    }
+    if !elf.starts_with(b"\x7fELF") {
+        bail!("The file doesn't start with the ELF magic number, it's not an ELF file");
+    }
+    let truncated = || {
+        format!(
+            "The file is only {} bytes, it's probably incomplete - rebuild it",
+            elf.len()
+        )
+    };
+    if elf.len() < MIN_SIZE {
+        bail!(truncated());
+    }
+
+    // the parser panics on section headers beyond the end of the file
+    let file = ElfFile::new(elf).map_err(anyhow::Error::msg)?;
+    let section_headers_end = file.header.pt2.sh_offset()
+        + u64::from(file.header.pt2.sh_entry_size()) * u64::from(file.header.pt2.sh_count());
+    if section_headers_end > elf.len() as u64 {
+        bail!(truncated());
+    }
+    Ok(())
+}
+
+/// Decodes the `.stack_sizes` sections, calling `on_entry` with the section index, offset, address and stack
+/// size of every record
+///
+/// `addresses` resolves the symbols of relocations, `relocate_base` is subtracted from the addresses. Records
+/// that can't be decoded or claim more than `max_frame` bytes are described in `malformed` and skipped.
+fn decode_stack_sizes(
+    elf: &ElfFile,
+    have_32_bit_addresses: bool,
+    addresses: &[u64],
+    relocate_base: u64,
+    max_frame: u64,
+    malformed: &mut Vec<String>,
+    mut on_entry: impl FnMut(usize, u64, u64, u64),
+) -> anyhow::Result<()> {
+    // relocatable objects contain one `.stack_sizes` section per function section
+    for (index, stack_sizes) in elf.section_iter().enumerate() {
+        if stack_sizes.get_name(elf) != Ok(".stack_sizes") {
+            continue;
+        }
+
+        let relocations = relocations(elf, index, stack_sizes.address())?;
+        let data = stack_sizes.raw_data(elf);
+        let end = data.len() as u64;
+        let mut cursor = Cursor::new(data);
+
+        while cursor.position() < end {
+            let offset = cursor.position();
+            let address = if have_32_bit_addresses {
+                cursor.read_u32::<LE>().map(u64::from)
+            } else {
+                cursor.read_u64::<LE>()
+            };
+            let Ok(mut address) = address else {
+                malformed.push(format!(
+                    "section {index} offset {offset:#x}: truncated record, skipping it"
+                ));
+                break;
+            };
+            if let Some(&(symbol, addend)) = relocations.get(&offset) {
+                let Some(&symbol_address) = addresses.get(symbol) else {
+                    bail!("`.stack_sizes` relocation refers to unknown symbol {symbol}");
+                };
+                address = symbol_address.wrapping_add(addend.unwrap_or(address));
+            }
+            let address = address.wrapping_sub(relocate_base);
+            // an overlong or truncated encoding leaves nothing to resynchronize on, the rest of the section is lost
+            let stack = match leb128::read::unsigned(&mut cursor) {
+                Ok(stack) => stack,
+                Err(err) => {
+                    malformed.push(format!(
+                        "section {index} offset {offset:#x}: invalid stack size ({err}), skipping the rest of the section"
+                    ));
+                    break;
+                }
+            };
+            if stack > max_frame {
+                malformed.push(format!(
+                    "section {index} offset {offset:#x}: implausible stack size of {stack} bytes for address {address:#x}, skipping it"
+                ));
+                continue;
+            }
+
+            on_entry(index, offset, address, stack);
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses an executable ELF file and returns a list of functions and their stack usage
+///
+/// With `thumb_fixup` an address also matches a function whose address differs only in the thumb bit.
+pub fn analyze_executable(elf: &[u8], thumb_fixup: bool) -> anyhow::Result<Functions<'_>> {
+    analyze_with_symbols(elf, thumb_fixup, &[], 0, MAX_FRAME)
+}
+
+/// Like `analyze_executable`, with the code `symbols` of a linker map added where the symbol table has none
+///
+/// That recovers the functions of stripped executables. `relocate_base` is subtracted from the `.stack_sizes`
+/// addresses before they're matched, records of more than `max_frame` bytes are skipped as malformed.
+pub fn analyze_with_symbols<'a>(
+    elf: &'a [u8],
+    thumb_fixup: bool,
+    symbols: &'a [MapSymbol],
+    relocate_base: u64,
+    max_frame: u64,
+) -> anyhow::Result<Functions<'a>> {
+    check_plausible(elf)?;
+    let elf = &ElfFile::new(elf).map_err(anyhow::Error::msg)?;
+
+    let bases = section_bases(elf);
+    let mut have_32_bit_addresses = false;
+    let Symbols {
+        undefined,
+        mut defined,
+        addresses,
+    } = if let Some(section) = elf.find_section_by_name(".symtab") {
+        match section.get_data(elf).map_err(anyhow::Error::msg)? {
+            SectionData::SymbolTable32(entries) => {
+                have_32_bit_addresses = true;
+
+                process_symtab_exec(entries, elf, &bases, thumb_fixup)?
+            }
+
+            SectionData::SymbolTable64(entries) => {
+                process_symtab_exec(entries, elf, &bases, thumb_fixup)?
+            }
+            _ => bail!("malformed .symtab section"),
+        }
+    } else {
+        Symbols::default()
+    };
+
+    let code = code_ranges(elf, &bases);
+    for symbol in symbols {
+        if !code
+            .iter()
+            .any(|(lo, hi)| (*lo..*hi).contains(&(symbol.address & !1)))
+        {
+            continue;
+        }
+        // the symbol table wins, the map only fills in what's missing
+        if function_at(&mut defined, symbol.address, thumb_fixup).is_none() {
+            defined.insert(
+                symbol.address,
+                Function {
+                    names: vec![&symbol.name],
+                    size: symbol.size,
+                    symbol_size: symbol.size,
+                    extent: None,
+                    stack: None,
+                    estimated: false,
+                },
+            );
+        }
+    }
+
+    compute_extents(elf, &bases, &mut defined);
+
+    let mut matched = 0;
+    let mut unmatched = Vec::new();
+    let mut entries = Vec::new();
+    let mut malformed = Vec::new();
+    decode_stack_sizes(
+        elf,
+        have_32_bit_addresses,
+        &addresses,
+        relocate_base,
+        max_frame,
+        &mut malformed,
+        |section, offset, address, stack| {
+            let function = if let Some(sym) = function_at(&mut defined, address, thumb_fixup) {
+                sym.stack = Some(stack);
+                matched += 1;
+                sym.names.first().copied()
+            } else {
+                unmatched.push((address, stack));
+                None
+            };
+            entries.push(StackSizesEntry {
+                section,
+                offset,
+                address,
+                stack,
+                function,
+            });
+        },
+    )?;
+
+    Ok(Functions {
+        have_32_bit_addresses,
+        undefined,
+        defined,
+        matched,
+        unmatched,
+        entries,
+        malformed,
+    })
+}
+
+/// Streams the function symbols of an executable with their stack usage, without collecting them like
+/// [`analyze_executable`] does
+///
+/// Yields the address and the function of every defined function symbol in symbol table order. Each symbol
+/// comes on its own: aliases aren't merged into one function, zero sizes aren't filled in from the extent
+/// (`extent` is `None`) and there's no thumb fixup. The `.stack_sizes` records are still decoded up front,
+/// into a map from address to stack size, malformed records are skipped silently.
+pub fn functions_iter(
+    elf: &[u8],
+) -> anyhow::Result<Box<dyn Iterator<Item = (u64, Function<'_>)> + '_>> {
+    check_plausible(elf)?;
+    let elf = ElfFile::new(elf).map_err(anyhow::Error::msg)?;
+    let Some(symtab) = elf.find_section_by_name(".symtab") else {
+        return Ok(Box::new(std::iter::empty()));
+    };
+
+    let bases = section_bases(&elf);
+    Ok(match symtab.get_data(&elf).map_err(anyhow::Error::msg)? {
+        SectionData::SymbolTable32(entries) => stream_symtab(entries, elf, bases, true)?,
+        SectionData::SymbolTable64(entries) => stream_symtab(entries, elf, bases, false)?,
+        _ => bail!("malformed .symtab section"),
+    })
+}
+
+fn stream_symtab<'a, E: Entry>(
+    entries: &'a [E],
+    elf: ElfFile<'a>,
+    bases: Vec<u64>,
+    have_32_bit_addresses: bool,
+) -> anyhow::Result<Box<dyn Iterator<Item = (u64, Function<'a>)> + 'a>> {
+    let relocatable = elf.header.pt2.type_().as_type() == header::Type::Relocatable;
+    let address = move |entry: &E| {
+        if relocatable {
+            entry.value() + bases.get(usize::from(entry.shndx())).copied().unwrap_or(0)
+        } else {
+            entry.value()
+        }
+    };
+
+    let addresses: Vec<u64> = entries.iter().map(&address).collect();
+    let mut stacks = HashMap::new();
+    decode_stack_sizes(
+        &elf,
+        have_32_bit_addresses,
+        &addresses,
+        0,
+        MAX_FRAME,
+        &mut Vec::new(),
+        |_, _, address, stack| {
+            stacks.insert(address, stack);
+        },
+    )?;
+
+    Ok(Box::new(entries.iter().filter_map(move |entry| {
+        let value = address(entry);
+        let size = entry.size();
+        if entry.get_type() != Ok(Type::Func) || entry.shndx() == 0 || (value == 0 && size == 0) {
+            return None;
+        }
+        let name = entry.get_name(&elf).ok()?;
+        Some((
+            value,
+            Function {
+                names: vec![name],
+                size,
+                symbol_size: size,
+                extent: None,
+                stack: stacks.get(&value).copied(),
+                estimated: false,
+            },
+        ))
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Built from `fixtures/functions.s` by `fixtures/build.sh`
+    const X86_64_OBJECT: &[u8] = include_bytes!("../fixtures/functions-x86_64.o");
+    /// Same source as `X86_64_OBJECT`, its `.stack_sizes` relocations are `.rel` with the addends in place
+    const I386_OBJECT: &[u8] = include_bytes!("../fixtures/functions-i386.o");
+    /// Built from `fixtures/malformed.s`, with stack sizes right at and beyond `MAX_FRAME`
+    const MALFORMED_X86_64_OBJECT: &[u8] = include_bytes!("../fixtures/malformed-x86_64.o");
+    const MALFORMED_I386_OBJECT: &[u8] = include_bytes!("../fixtures/malformed-i386.o");
+
+    /// Copies the ELF to the heap, `xmas-elf` needs its headers aligned unlike `include_bytes!` places them
+    fn elf(bytes: &[u8]) -> Vec<u8> {
+        bytes.to_vec()
+    }
+
+    fn function<'f, 'a>(functions: &'f Functions<'a>, name: &str) -> (u64, &'f Function<'a>) {
+        functions
+            .defined
+            .iter()
+            .find(|(_, f)| f.names().contains(&name))
+            .map(|(&address, f)| (address, f))
+            .unwrap_or_else(|| panic!("no function `{name}`"))
+    }
+
+    fn names<'a>(functions: &[(u64, &Function<'a>)]) -> Vec<&'a str> {
+        functions.iter().map(|(_, f)| f.names()[0]).collect()
+    }
+
+    #[test]
+    fn orders_by_stack_size() {
+        let elf = elf(X86_64_OBJECT);
+        let functions = analyze_executable(&elf, false).unwrap();
+        // `after` and `tail` have no stack size and are ordered by address
+        assert_eq!(
+            names(&functions.by_stack_desc()),
+            ["huge", "first", "zero", "after", "tail"]
+        );
+    }
+
+    #[test]
+    fn orders_by_code_size() {
+        let elf = elf(X86_64_OBJECT);
+        let functions = analyze_executable(&elf, false).unwrap();
+        // `zero` and `huge` are both 2 bytes, `zero` comes first by its address
+        assert_eq!(
+            names(&functions.by_code_desc()),
+            ["first", "tail", "zero", "huge", "after"]
+        );
+    }
+
+    #[test]
+    fn ties_are_broken_by_address_and_name() {
+        let mut sizes = vec![
+            (8, 0x20, "b"),
+            (8, 0x10, "c"),
+            (16, 0x30, "d"),
+            (8, 0x20, "a"),
+        ];
+        sizes.sort_by(|&a, &b| largest_first(a, b));
+        assert_eq!(
+            sizes,
+            [
+                (16, 0x30, "d"),
+                (8, 0x10, "c"),
+                (8, 0x20, "a"),
+                (8, 0x20, "b")
+            ]
+        );
+    }
+
+    #[test]
+    fn streams_the_same_stack_sizes() {
+        for object in [X86_64_OBJECT, I386_OBJECT] {
+            let elf = elf(object);
+            let functions = analyze_executable(&elf, false).unwrap();
+            let streamed: Vec<_> = functions_iter(&elf).unwrap().collect();
+            assert_eq!(streamed.len(), functions.defined.len());
+            for (address, streamed) in streamed {
+                let (expected_address, expected) = function(&functions, streamed.names()[0]);
+                assert_eq!(address, expected_address);
+                assert_eq!(streamed.stack(), expected.stack());
+                assert_eq!(streamed.symbol_size(), expected.symbol_size());
+            }
+        }
+    }
+
+    #[test]
+    fn streamed_functions_keep_the_symbol_size() {
+        let elf = elf(X86_64_OBJECT);
+        let (_, zero) = functions_iter(&elf)
+            .unwrap()
+            .find(|(_, f)| f.names() == ["zero"])
+            .unwrap();
+        assert_eq!(zero.size(), 0);
+        assert_eq!(zero.extent(), None);
+        assert_eq!(zero.stack(), Some(8));
+    }
+
+    #[test]
+    fn map_symbols_fill_in_what_the_symbol_table_lacks() {
+        let elf = elf(X86_64_OBJECT);
+        let first = function(&analyze_executable(&elf, false).unwrap(), "first").0;
+        let symbol = |name: &str, address| MapSymbol {
+            name: name.to_string(),
+            address,
+            size: 2,
+        };
+        let symbols = [
+            symbol("map_first", first),
+            // GNU ld lists both the input section and the demangled symbol
+            symbol("_ZN3map6inside17h0123456789abcdefE", first + 2),
+            symbol("map::inside", first + 2),
+            symbol("outside", 0x10_0000),
+        ];
+
+        let functions = analyze_with_symbols(&elf, false, &symbols, 0, MAX_FRAME).unwrap();
+        assert_eq!(function(&functions, "first").1.names(), ["first"]);
+        let (address, inside) = function(&functions, "_ZN3map6inside17h0123456789abcdefE");
+        assert_eq!(address, first + 2);
+        assert_eq!(inside.names().len(), 1);
+        assert_eq!(inside.size(), 2);
+        // the map symbol splits the extent of `first`
+        assert_eq!(function(&functions, "first").1.extent(), Some(2));
+        assert!(!functions.defined.contains_key(&0x10_0000));
+    }
+
+    #[test]
+    fn zero_sizes_reach_up_to_the_next_symbol() {
+        let elf = elf(X86_64_OBJECT);
+        let functions = analyze_executable(&elf, false).unwrap();
+        let (_, zero) = function(&functions, "zero");
+        assert_eq!(zero.size(), 2);
+        assert_eq!(zero.extent(), Some(2));
+        assert_eq!(function(&functions, "first").1.size(), 4);
+    }
+
+    #[test]
+    fn zero_sizes_reach_up_to_the_section_end() {
+        let elf = elf(X86_64_OBJECT);
+        let functions = analyze_executable(&elf, false).unwrap();
+        let (_, tail) = function(&functions, "tail");
+        assert_eq!(tail.size(), 3);
+        assert_eq!(tail.extent(), Some(3));
+    }
+
+    #[test]
+    fn sizes_beyond_the_section_are_clamped() {
+        let elf = elf(X86_64_OBJECT);
+        let functions = analyze_executable(&elf, false).unwrap();
+        let (_, huge) = function(&functions, "huge");
+        assert_eq!(huge.size(), 2);
+        assert_eq!(huge.extent(), Some(2));
+    }
+
+    #[test]
+    fn symbol_sizes_are_kept() {
+        let elf = elf(X86_64_OBJECT);
+        let functions = analyze_executable(&elf, false).unwrap();
+        let (_, zero) = function(&functions, "zero");
+        assert_eq!(zero.symbol_size(), 0);
+        let (_, huge) = function(&functions, "huge");
+        assert_eq!(huge.symbol_size(), 100);
+        assert_eq!(function(&functions, "first").1.symbol_size(), 4);
+    }
+
+    fn resolves_relocations(object: &[u8]) {
+        let elf = elf(object);
+        let functions = analyze_executable(&elf, false).unwrap();
+        let expected = [("first", 0x0, 16), ("zero", 0x4, 8), ("huge", 0x10, 48)];
+        for (name, address, stack) in expected {
+            let (at, f) = function(&functions, name);
+            assert_eq!(at, address, "address of `{name}`");
+            assert_eq!(f.stack(), Some(stack), "stack of `{name}`");
+        }
+        assert_eq!(function(&functions, "after").1.stack(), None);
+        assert_eq!(function(&functions, "tail").1.stack(), None);
+        assert_eq!(functions.matched, expected.len());
+        assert!(functions.unmatched.is_empty());
+        assert!(functions.malformed.is_empty());
+    }
+
+    #[test]
+    fn resolves_rela_relocations() {
+        resolves_relocations(X86_64_OBJECT);
+    }
+
+    #[test]
+    fn resolves_rel_relocations() {
+        resolves_relocations(I386_OBJECT);
+    }
+
+    fn skips_malformed_records(object: &[u8]) {
+        let elf = elf(object);
+        let functions = analyze_executable(&elf, false).unwrap();
+        assert_eq!(functions.malformed.len(), 2, "{:?}", functions.malformed);
+        assert!(functions.malformed[0].contains("implausible stack size"));
+        assert!(functions.malformed[1].contains("invalid stack size"));
+
+        // neither record is reported, the one after the oversized record still is, as is a frame of `MAX_FRAME`
+        assert_eq!(function(&functions, "first").1.stack(), None);
+        assert_eq!(function(&functions, "second").1.stack(), Some(24));
+        assert_eq!(function(&functions, "third").1.stack(), None);
+        assert_eq!(function(&functions, "fourth").1.stack(), Some(MAX_FRAME));
+        assert_eq!(functions.matched, 2);
+        assert!(functions.unmatched.is_empty());
+        let stacks: Vec<_> = functions.entries.iter().map(|e| e.stack).collect();
+        assert_eq!(stacks, [24, MAX_FRAME]);
+    }
+
+    #[test]
+    fn skips_malformed_records_of_64_bit_objects() {
+        skips_malformed_records(MALFORMED_X86_64_OBJECT);
+    }
+
+    #[test]
+    fn skips_malformed_records_of_32_bit_objects() {
+        skips_malformed_records(MALFORMED_I386_OBJECT);
+    }
+
+    #[test]
+    fn the_frame_limit_is_configurable() {
+        let elf = elf(MALFORMED_X86_64_OBJECT);
+        let functions = analyze_with_symbols(&elf, false, &[], 0, 24).unwrap();
+        assert_eq!(function(&functions, "second").1.stack(), Some(24));
+        assert_eq!(function(&functions, "fourth").1.stack(), None);
+        assert_eq!(functions.malformed.len(), 3, "{:?}", functions.malformed);
+
+        let functions = analyze_with_symbols(&elf, false, &[], 0, MAX_FRAME + 1).unwrap();
+        assert_eq!(function(&functions, "first").1.stack(), Some(MAX_FRAME + 1));
+    }
+}
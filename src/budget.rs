@@ -0,0 +1,107 @@
+//! Stack budgets like the `--fail-over` and `--fail-over-total` checks of the command line tool
+
+use crate::analysis::Functions;
+
+/// Outcome of the `--fail-over` and `--fail-over-total` budgets
+#[derive(Debug, Default)]
+pub struct BudgetReport {
+    /// Stack a single function may need
+    pub per_function: Option<u64>,
+    /// Stack all frames together may add up to
+    pub total_limit: Option<u64>,
+    /// Names and stack sizes of the functions exceeding `per_function`
+    pub over: Vec<(String, u64)>,
+    /// Sum of the stack frames of all checked functions
+    pub total: u64,
+}
+
+impl BudgetReport {
+    pub fn new(per_function: Option<u64>, total_limit: Option<u64>) -> BudgetReport {
+        BudgetReport {
+            per_function,
+            total_limit,
+            ..BudgetReport::default()
+        }
+    }
+
+    /// Checks the names and stack sizes of `functions` against the budgets
+    ///
+    /// Can be called for several ELFs, the total covers all of them.
+    pub fn check(&mut self, functions: impl IntoIterator<Item = (String, u64)>) {
+        for (name, stack) in functions {
+            self.total += stack;
+            if self
+                .per_function
+                .map(|limit| stack > limit)
+                .unwrap_or(false)
+            {
+                self.over.push((name, stack));
+            }
+        }
+    }
+
+    /// The total as a violation of `total_limit`, empty if it's within the limit
+    pub fn over_total(&self) -> Vec<(String, u64)> {
+        match self.total_limit {
+            Some(limit) if self.total > limit => {
+                vec![(String::from("total of all stack frames"), self.total)]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether no function exceeds `per_function` and the total stays within `total_limit`
+    pub fn passed(&self) -> bool {
+        self.over.is_empty() && self.over_total().is_empty()
+    }
+}
+
+/// Checks the defined `functions` against a stack budget per function and for all of them together
+///
+/// Functions are named like the report shows them, their demangled names separated by spaces. Functions
+/// without a stack size count as zero.
+pub fn check_budget(
+    functions: &Functions,
+    per_function: Option<u64>,
+    total: Option<u64>,
+) -> BudgetReport {
+    let mut report = BudgetReport::new(per_function, total);
+    report.check(functions.defined.values().map(|function| {
+        let names: Vec<String> = function
+            .names()
+            .iter()
+            .map(|name| format!("{:#}", rustc_demangle::demangle(name)))
+            .collect();
+        (names.join(" "), function.stack().unwrap_or(0))
+    }));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::analyze_executable;
+
+    /// Built from `fixtures/functions.s` by `fixtures/build.sh`
+    const X86_64_OBJECT: &[u8] = include_bytes!("../fixtures/functions-x86_64.o");
+
+    #[test]
+    fn reports_functions_over_the_limit() {
+        // copied to the heap, `xmas-elf` needs its headers aligned
+        let elf = X86_64_OBJECT.to_vec();
+        let functions = analyze_executable(&elf, false).unwrap();
+
+        let report = check_budget(&functions, Some(16), Some(100));
+        assert_eq!(report.over, [(String::from("huge"), 48)]);
+        assert_eq!(report.total, 16 + 8 + 48);
+        assert!(report.over_total().is_empty());
+        assert!(!report.passed());
+
+        let report = check_budget(&functions, Some(48), Some(64));
+        assert!(report.over.is_empty());
+        assert_eq!(report.over_total().len(), 1);
+        assert!(!report.passed());
+
+        assert!(check_budget(&functions, None, None).passed());
+    }
+}
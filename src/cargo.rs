@@ -0,0 +1,922 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    io::{BufRead, BufReader},
+    path::{Component, Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+};
+
+use anyhow::bail;
+use cargo_project::{Artifact, Profile, Project};
+use log::{debug, info};
+use toml::Value;
+use xmas_elf::ElfFile;
+
+use crate::{
+    analysis,
+    elf_data::ElfData,
+    json,
+    linker::{self, Dialect},
+    report, Args, Failure, Lto, PanicStrategy,
+};
+
+/// An ELF to analyze
+pub struct Build {
+    pub path: PathBuf,
+    pub elf: ElfData,
+    /// Notes about how the ELF was built, shown above the report
+    pub notes: Vec<String>,
+}
+
+/// Builds the requested binary/example with stack size information and reads the resulting ELF
+pub fn build_artifact(args: &Args) -> anyhow::Result<Build> {
+    let (kind, file) = match (&args.example, &args.bin, &args.bench) {
+        (Some(f), None, None) => ("example", f),
+        (None, Some(f), None) => ("bin", f),
+        (None, None, Some(f)) => ("bench", f),
+        _ => bail!("Please specify either --example <NAME>, --bin <NAME> or --bench <NAME>."),
+    };
+
+    build_target(args, kind, file)
+}
+
+/// Builds every binary, example, test and bench target of the package
+pub fn build_all_targets(args: &Args) -> anyhow::Result<Vec<Build>> {
+    let project = Project::query(env::current_dir()?)?;
+    let targets = package_targets(args, project.toml())?;
+    if targets.is_empty() {
+        bail!("The package doesn't have any binary, example, test or bench targets");
+    }
+
+    targets
+        .iter()
+        .enumerate()
+        .map(|(i, (kind, name))| {
+            eprintln!("[{}/{}] Building {kind} `{name}`", i + 1, targets.len());
+            build_target(args, kind, name)
+        })
+        .collect()
+}
+
+/// Prints the binary, example, test and bench targets of the package, of every member with `--workspace` or in
+/// a virtual workspace
+pub fn list_targets(args: &Args) -> anyhow::Result<()> {
+    // the root of a virtual workspace isn't a package, list its members then
+    let packages = match Project::query(env::current_dir()?) {
+        Ok(project) if !args.workspace => package(args, project.toml())?.into_iter().collect(),
+        _ => workspace_packages(args)?,
+    };
+
+    for (i, package) in packages.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("Package `{}`:", package.name);
+        if package.targets.is_empty() {
+            println!("  no binary, example, test or bench targets");
+        }
+        let width = package
+            .targets
+            .iter()
+            .map(|(_, name)| report::display_width(name))
+            .max()
+            .unwrap_or(0);
+        for (kind, name) in &package.targets {
+            match package.required_features.get(&(kind.clone(), name.clone())) {
+                Some(features) => println!(
+                    "  {kind:7} {}  requires features: {}",
+                    report::pad(name, width),
+                    features.join(", ")
+                ),
+                None => println!("  {kind:7} {name}"),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Builds the binaries of every workspace member but the `--exclude`d ones, only `--bin` if given
+pub fn build_workspace(args: &Args) -> anyhow::Result<Vec<Build>> {
+    let mut selected = Vec::new();
+    for package in workspace_packages(args)? {
+        if args.exclude.contains(&package.name) {
+            info!("Skipping excluded package `{}`", package.name);
+            continue;
+        }
+        for (kind, name) in &package.targets {
+            if kind == "bin" && args.bin.as_ref().is_none_or(|bin| bin == name) {
+                selected.push((package.name.clone(), package.manifest.clone(), name.clone()));
+            }
+        }
+    }
+    if selected.is_empty() {
+        bail!("No workspace member has a binary to build");
+    }
+
+    let root = env::current_dir()?;
+    let mut builds = Vec::new();
+    for (i, (package, manifest, bin)) in selected.iter().enumerate() {
+        // the project and its configuration are looked up from the current directory
+        let Some(dir) = manifest.parent() else {
+            continue;
+        };
+        env::set_current_dir(dir)?;
+        eprintln!(
+            "[{}/{}] Building `{package}` bin `{bin}`",
+            i + 1,
+            selected.len()
+        );
+        let built = build_target(args, "bin", bin);
+        env::set_current_dir(&root)?;
+        builds.push(built?);
+    }
+    Ok(builds)
+}
+
+/// A workspace member as reported by `cargo metadata`
+struct Package {
+    name: String,
+    manifest: PathBuf,
+    /// Kind and name of the binary, example, test and bench targets
+    targets: Vec<(String, String)>,
+    /// `required-features` of the targets, keyed by kind and name
+    required_features: HashMap<(String, String), Vec<String>>,
+}
+
+/// Queries the workspace members and their targets
+fn workspace_packages(args: &Args) -> anyhow::Result<Vec<Package>> {
+    let output = Command::new("cargo")
+        .args(["metadata", "--format-version=1", "--no-deps"])
+        .args(lock_flags(args))
+        .output()?;
+    if !output.status.success() {
+        bail!(
+            "`cargo metadata` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let metadata = json::parse(std::str::from_utf8(&output.stdout)?)?;
+    let mut packages = Vec::new();
+    for package in metadata
+        .get("packages")
+        .and_then(|p| p.as_array())
+        .unwrap_or_default()
+    {
+        let (Some(name), Some(manifest)) = (
+            package.get("name").and_then(|n| n.as_str()),
+            package.get("manifest_path").and_then(|m| m.as_str()),
+        ) else {
+            continue;
+        };
+
+        let mut targets = Vec::new();
+        let mut required_features = HashMap::new();
+        for target in package
+            .get("targets")
+            .and_then(|t| t.as_array())
+            .unwrap_or_default()
+        {
+            let Some(name) = target.get("name").and_then(|n| n.as_str()) else {
+                continue;
+            };
+            let kinds = target
+                .get("kind")
+                .and_then(|k| k.as_array())
+                .unwrap_or_default();
+            if let Some(kind) = ["bin", "example", "test", "bench"]
+                .into_iter()
+                .find(|kind| kinds.iter().any(|k| k.as_str() == Some(kind)))
+            {
+                targets.push((kind.to_string(), name.to_string()));
+                let required: Vec<String> = target
+                    .get("required-features")
+                    .and_then(|f| f.as_array())
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|f| f.as_str())
+                    .map(String::from)
+                    .collect();
+                if !required.is_empty() {
+                    required_features.insert((kind.to_string(), name.to_string()), required);
+                }
+            }
+        }
+
+        packages.push(Package {
+            name: name.to_string(),
+            manifest: PathBuf::from(manifest),
+            targets,
+            required_features,
+        });
+    }
+    Ok(packages)
+}
+
+/// Queries the workspace member with the manifest `manifest`
+fn package(args: &Args, manifest: &Path) -> anyhow::Result<Option<Package>> {
+    let manifest = manifest.canonicalize()?;
+    Ok(workspace_packages(args)?
+        .into_iter()
+        .find(|p| p.manifest.canonicalize().ok().as_ref() == Some(&manifest)))
+}
+
+/// Reads the `[features]` table of the package in the current directory, the features each one enables
+pub fn package_features() -> anyhow::Result<BTreeMap<String, Vec<String>>> {
+    let project = Project::query(env::current_dir()?)?;
+    let manifest: Value = std::fs::read_to_string(project.toml())?.parse()?;
+    Ok(manifest
+        .get("features")
+        .and_then(|f| f.as_table())
+        .map(|table| {
+            table
+                .iter()
+                .map(|(name, enables)| {
+                    let enables = enables
+                        .as_array()
+                        .map(|e| {
+                            e.iter()
+                                .filter_map(|f| f.as_str())
+                                .map(String::from)
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    (name.clone(), enables)
+                })
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Queries the binary, example, test and bench targets of the package with the manifest `manifest`
+fn package_targets(args: &Args, manifest: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    Ok(package(args, manifest)?
+        .map(|p| p.targets)
+        .unwrap_or_default())
+}
+
+/// Adds the `required-features` of the `kind` target `file` that `--features` is missing
+///
+/// Without them cargo skips building the target.
+fn with_required_features(
+    args: &Args,
+    manifest: &Path,
+    kind: &str,
+    file: &str,
+) -> anyhow::Result<Args> {
+    let mut args = args.clone();
+    if args.all_features {
+        return Ok(args);
+    }
+    let required = package(&args, manifest)?
+        .and_then(|mut p| {
+            p.required_features
+                .remove(&(kind.to_string(), file.to_string()))
+        })
+        .unwrap_or_default();
+
+    let mut features: Vec<String> = args
+        .features
+        .as_deref()
+        .unwrap_or_default()
+        .split([' ', ','])
+        .filter(|f| !f.is_empty())
+        .map(String::from)
+        .collect();
+    let missing: Vec<String> = required
+        .into_iter()
+        .filter(|f| !features.contains(f))
+        .collect();
+    if !missing.is_empty() {
+        info!(
+            "Enabling the required features of {kind} `{file}`: {}",
+            missing.join(", ")
+        );
+        features.extend(missing);
+        args.features = Some(features.join(","));
+    }
+    Ok(args)
+}
+
+/// Builds the `kind` target `file` with stack size information and reads the resulting ELF
+fn build_target(args: &Args, kind: &str, file: &str) -> anyhow::Result<Build> {
+    let host = match (rustc_version::version_meta(), &args.assume_target) {
+        (Ok(meta), _) => meta.host,
+        (Err(e), Some(target)) => {
+            info!("Can't query rustc ({e}), assuming {target}");
+            target.clone()
+        }
+        (Err(e), None) => {
+            return Err(anyhow::Error::from(e)
+                .context("Can't query rustc, pass `--assume-target <TRIPLE>`"))
+        }
+    };
+    let cwd = env::current_dir()?;
+    let project = Project::query(cwd)?;
+    let args = &with_required_features(args, project.toml(), kind, file)?;
+    let spec = args
+        .target_json
+        .as_ref()
+        .map(|path| path.display().to_string());
+    let target = target_name(
+        spec.as_deref()
+            .or(args.target.as_deref())
+            .or(project.target())
+            .unwrap_or(&host),
+    );
+    info!("Target: {target} (host: {host})");
+
+    let config = if args.no_cargo_config {
+        eprintln!(
+            "Ignoring .cargo/config.toml: its rustflags, linker and profile settings aren't taken into account, \
+             the build may differ from the usual one. Cargo itself still reads the file, e.g. `target.<TRIPLE>.rustflags`"
+        );
+        None
+    } else {
+        config()?
+    };
+    let mut rustflags: Vec<String> = config
+        .as_ref()
+        .and_then(|c| c.get("build"))
+        .and_then(|b| b.get("rustflags"))
+        .and_then(|rf| rf.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v.as_str())
+        .map(String::from)
+        .collect();
+    info!("Rustflags from .cargo/config.toml: {rustflags:?}");
+
+    let mut notes = Vec::new();
+    let mut codegen = Vec::new();
+    if let Some(panic) = args.panic {
+        codegen.push(format!("panic={panic}"));
+        notes.push(format!("Panic strategy: {panic} (--panic)"));
+    } else {
+        let (panic, source) = panic_strategy(project.toml(), config.as_ref(), target);
+        notes.push(format!("Panic strategy: {panic} ({source})"));
+    }
+    let (lto, source) = match args.lto {
+        Some(lto) => (lto, "--lto"),
+        None => lto(project.toml(), config.as_ref()),
+    };
+    if lto != Lto::Off {
+        notes.push(format!("LTO: {lto} ({source}), functions inlined across crates are attributed to the function they were merged into"));
+    } else if args.lto.is_some() {
+        notes.push(format!("LTO: {lto} ({source})"));
+    }
+    if let Some(cpu) = &args.target_cpu {
+        codegen.push(format!("target-cpu={cpu}"));
+    }
+    if let Some(features) = &args.target_feature {
+        codegen.push(format!("target-feature={features}"));
+    }
+    for flag in &codegen {
+        rustflags.push(String::from("-C"));
+        rustflags.push(flag.clone());
+    }
+    info!("Codegen flags: {codegen:?}");
+
+    if let Some(template) = &args.build_command {
+        if !template.contains("{config}") && !template.contains("{rustflags}") {
+            eprintln!("The build command uses neither `{{config}}` nor `{{rustflags}}`, stack sizes might not be emitted");
+        }
+    }
+
+    if args.no_build {
+        if matches!(kind, "bench" | "test") && args.out_override.is_none() {
+            bail!("The path of {kind} `{file}` can't be derived without building it, use --elf or --out-override");
+        }
+        let path = artifact_path(args, &project, kind, file, target, &host)?;
+        info!("Artifact: {}", path.display());
+        if !path.exists() {
+            bail!(
+                "{} doesn't exist, build it first by running without --no-build",
+                path.display()
+            );
+        }
+        let elf = ElfData::load(&path)?;
+        // a plain `cargo build` doesn't emit stack sizes, every function would be shown without one
+        if !args.from_cfi && !has_stack_sizes(&elf)? {
+            eprintln!(
+                "{} has no `.stack_sizes` section, it wasn't built by ssize - run without --no-build to rebuild it with stack sizes",
+                path.display()
+            );
+        }
+        return Ok(Build { path, elf, notes });
+    }
+
+    // if we can't tell which linker is used try both script dialects and keep the one producing `.stack_sizes`
+    let effective = effective_rustflags(config.as_ref(), target)
+        .map(|(flags, _)| flags)
+        .unwrap_or_default();
+    let dialects = match linker::detect(config.as_ref(), target, &effective) {
+        // no linker script is passed
+        _ if args.from_cfi => vec![Dialect::Gnu],
+        Some(dialect) => {
+            debug!("Detected linker: {dialect}");
+            vec![dialect]
+        }
+        // custom attributes make the script the same for every dialect
+        None if args.section_attrs.is_some() => vec![Dialect::Gnu],
+        None => {
+            debug!("Couldn't detect the linker, trying all linker script dialects");
+            vec![Dialect::Gnu, Dialect::Lld]
+        }
+    };
+
+    let driver = linker::is_driver(config.as_ref(), target, &effective);
+    if driver {
+        debug!(
+            "The configured linker is a compiler driver, passing the linker script using `-Wl,`"
+        );
+    }
+
+    // the executable of the last successful build, used without stack sizes if no dialect produces them
+    let mut artifact = None;
+    for (i, &dialect) in dialects.iter().enumerate() {
+        let last = i == dialects.len() - 1;
+
+        let (status, executable) = build(args, kind, file, target, &rustflags, dialect, driver)?;
+        if !status.success() {
+            if !last {
+                eprintln!("Build using the {dialect} linker script failed, retrying");
+                continue;
+            }
+            if artifact.is_some() {
+                eprintln!("Build using the {dialect} linker script failed");
+                break;
+            }
+
+            if args.offline || args.frozen {
+                bail!(Failure::build("The build failed - with --offline all dependencies need to be vendored or already downloaded"));
+            }
+            bail!(Failure::build("The build failed"));
+        }
+
+        let path = match (&args.out_override, executable) {
+            (None, Some(executable)) => executable,
+            _ if matches!(kind, "bench" | "test") && args.out_override.is_none() => {
+                bail!("Cargo didn't report the executable of {kind} `{file}`")
+            }
+            _ => artifact_path(args, &project, kind, file, target, &host)?,
+        };
+        info!("Artifact: {}", path.display());
+        let elf = match ElfData::load(&path) {
+            Ok(elf) => elf,
+            Err(_) if !last || artifact.is_some() => continue,
+            Err(err) => return Err(err.into()),
+        };
+
+        if args.from_cfi || has_stack_sizes(&elf)? {
+            if dialects.len() > 1 {
+                eprintln!("The {dialect} linker script produced the `.stack_sizes` section");
+            }
+            return Ok(Build { path, elf, notes });
+        }
+        artifact = Some((path, elf));
+    }
+    let Some((path, elf)) = artifact else {
+        bail!(Failure::build("The build failed"));
+    };
+
+    eprintln!("No `.stack_sizes` section found - stack sizes will be missing");
+    if let Some(attrs) = &args.section_attrs {
+        eprintln!("The linker didn't keep the section with the attributes `{attrs}`");
+    }
+    // builds using rustc's LTO come without `.stack_sizes`
+    if lto != Lto::Off {
+        eprintln!("LTO drops the stack sizes, analyze a build without it using `--lto off`");
+    }
+    Ok(Build { path, elf, notes })
+}
+
+/// Reads the `.cargo/config.toml` closest to the current directory if there is one
+///
+/// Like cargo, the parent directories are searched too, so members of a workspace find its configuration.
+pub fn config() -> anyhow::Result<Option<Value>> {
+    let cwd = env::current_dir()?;
+    for dir in cwd.ancestors() {
+        if let Ok(content) = std::fs::read_to_string(dir.join(".cargo/config.toml")) {
+            return Ok(Some(content.parse::<Value>()?));
+        }
+    }
+    Ok(None)
+}
+
+/// Figures out the panic strategy of the release build and where it comes from
+fn panic_strategy(
+    manifest: &Path,
+    config: Option<&Value>,
+    target: &str,
+) -> (PanicStrategy, &'static str) {
+    if let Some((rustflags, source)) = effective_rustflags(config, target) {
+        // rustc goes by the last `-C panic`
+        let mut previous = "";
+        let mut panic = None;
+        for flag in &rustflags {
+            let option = if previous == "-C" {
+                flag.as_str()
+            } else {
+                flag.strip_prefix("-C").unwrap_or("")
+            };
+            previous = flag;
+            match option {
+                "panic=abort" => panic = Some(PanicStrategy::Abort),
+                "panic=unwind" => panic = Some(PanicStrategy::Unwind),
+                _ => (),
+            }
+        }
+        if let Some(panic) = panic {
+            return (panic, source);
+        }
+    }
+
+    // the profile in `.cargo/config.toml` wins over the manifest, like for `lto`
+    let from_profile = |root: Option<&Value>| match root?
+        .get("profile")?
+        .get("release")?
+        .get("panic")?
+        .as_str()?
+    {
+        "abort" => Some(PanicStrategy::Abort),
+        "unwind" => Some(PanicStrategy::Unwind),
+        _ => None,
+    };
+    if let Some(panic) = from_profile(config) {
+        return (panic, ".cargo/config.toml");
+    }
+    if let Some(panic) = from_profile(root_manifest(manifest).as_ref()) {
+        return (panic, "profile.release");
+    }
+
+    if target.contains("-none") {
+        (PanicStrategy::Abort, "target default")
+    } else {
+        (PanicStrategy::Unwind, "target default")
+    }
+}
+
+/// The rustflags cargo passes to rustc for `target` and where they come from
+///
+/// Like cargo, the first of `CARGO_ENCODED_RUSTFLAGS`, `RUSTFLAGS`, `target.<TRIPLE>.rustflags` and
+/// `build.rustflags` that's set is used, the others are ignored.
+pub fn effective_rustflags(
+    config: Option<&Value>,
+    target: &str,
+) -> Option<(Vec<String>, &'static str)> {
+    if let Ok(encoded) = env::var("CARGO_ENCODED_RUSTFLAGS") {
+        let flags = encoded
+            .split('\x1f')
+            .filter(|f| !f.is_empty())
+            .map(String::from)
+            .collect();
+        return Some((flags, "CARGO_ENCODED_RUSTFLAGS"));
+    }
+    if let Ok(flags) = env::var("RUSTFLAGS") {
+        return Some((
+            flags.split_whitespace().map(String::from).collect(),
+            "RUSTFLAGS",
+        ));
+    }
+
+    // `rustflags` can be an array or a space-separated string
+    let from_table = |table: Option<&Value>| -> Option<Vec<String>> {
+        let rustflags = table?.get("rustflags")?;
+        match (rustflags.as_array(), rustflags.as_str()) {
+            (Some(flags), _) => Some(
+                flags
+                    .iter()
+                    .filter_map(|f| f.as_str())
+                    .map(String::from)
+                    .collect(),
+            ),
+            (_, Some(flags)) => Some(flags.split_whitespace().map(String::from).collect()),
+            _ => None,
+        }
+    };
+    let target_table = config
+        .and_then(|c| c.get("target"))
+        .and_then(|t| t.get(target));
+    if let Some(flags) = from_table(target_table) {
+        return Some((flags, "target.<TRIPLE>.rustflags"));
+    }
+    from_table(config.and_then(|c| c.get("build"))).map(|flags| (flags, "build.rustflags"))
+}
+
+/// Reads the manifest of the workspace root, profiles are only honored there
+///
+/// That's the outermost manifest defining `[workspace]`, or the package's own if there is none.
+fn root_manifest(manifest: &Path) -> Option<Value> {
+    let manifests = manifest.ancestors().skip(1).filter_map(|dir| {
+        let content = std::fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+        content.parse::<Value>().ok()
+    });
+    let mut root = None;
+    for (i, manifest) in manifests.enumerate() {
+        if i == 0 || manifest.get("workspace").is_some() {
+            root = Some(manifest);
+        }
+    }
+    root
+}
+
+/// Figures out the LTO setting of the release build and where it comes from
+fn lto(manifest: &Path, config: Option<&Value>) -> (Lto, &'static str) {
+    let from_profile = |root: Option<&Value>| {
+        let lto = root?.get("profile")?.get("release")?.get("lto")?;
+        match (lto.as_bool(), lto.as_str()) {
+            (Some(true), _) | (_, Some("fat")) => Some(Lto::Fat),
+            (_, Some("thin")) => Some(Lto::Thin),
+            (Some(false), _) | (_, Some("off")) => Some(Lto::Off),
+            _ => None,
+        }
+    };
+
+    if let Some(lto) = from_profile(config) {
+        return (lto, ".cargo/config.toml");
+    }
+    if let Some(lto) = from_profile(root_manifest(manifest).as_ref()) {
+        return (lto, "profile.release");
+    }
+    (Lto::Off, "default")
+}
+
+/// The `--offline`, `--locked` and `--frozen` flags to pass on to cargo
+///
+/// `--frozen` implies the other two, so they're left out then.
+pub fn lock_flags(args: &Args) -> Vec<&'static str> {
+    if args.frozen {
+        return vec!["--frozen"];
+    }
+    let mut flags = Vec::new();
+    if args.offline {
+        flags.push("--offline");
+    }
+    if args.locked {
+        flags.push("--locked");
+    }
+    flags
+}
+
+/// Runs `cargo build` with `-Z emit-stack-sizes` and the linker script for `dialect` injected
+///
+/// With a compiler `driver` as the linker the linker script options are wrapped in `-Wl,`. `--from-cfi` builds
+/// get neither, so they work on stable toolchains and with linkers the scripts don't support.
+/// Also returns the path of the executable built for the `kind` target `file` as reported by cargo
+fn build(
+    args: &Args,
+    kind: &str,
+    file: &str,
+    target: &str,
+    rustflags: &[String],
+    dialect: Dialect,
+    driver: bool,
+) -> anyhow::Result<(ExitStatus, Option<PathBuf>)> {
+    let mut rustflags = rustflags.to_vec();
+    let mut tmp_file = None;
+    if !args.from_cfi {
+        let mut path = std::env::temp_dir();
+        let tmp_dir = path.to_str().unwrap().replace('\\', "/");
+        let attrs = args.section_attrs.as_deref();
+        let script = dialect.file_name(attrs);
+        path.push(&script);
+        std::fs::write(&path, dialect.script(attrs))?;
+        debug!("Wrote {dialect} linker script to {}", path.display());
+        tmp_file = Some(path);
+
+        // `-Wl,` options reach the linker in order, so the search path has to come before the script
+        let wrap = if driver { "-Wl," } else { "" };
+        rustflags.extend([
+            String::from("-Z"),
+            String::from("emit-stack-sizes"),
+            String::from("-C"),
+            format!("link-arg={wrap}-L{tmp_dir}"),
+            String::from("-C"),
+            format!("link-arg={wrap}-T{script}"),
+        ]);
+    }
+    let config = format!(
+        "target.{target}.rustflags=[{}]",
+        rustflags
+            .iter()
+            .map(|flag| format!("\"{}\"", flag.replace('\\', "\\\\").replace('"', "\\\"")))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let cargo_res = if let Some(template) = &args.build_command {
+        let command = expand_template(template, &config, &rustflags.join(" "), kind, file);
+        info!("Running: {}", command.join(" "));
+        run_custom(&command, kind, file)
+    } else {
+        let mut cargo_args = vec![
+            String::from("--config"),
+            config,
+            String::from("build"),
+            String::from("--release"),
+            String::from("--message-format=json-render-diagnostics"),
+        ];
+
+        cargo_args.extend(lock_flags(args).into_iter().map(String::from));
+
+        if let Some(lto) = args.lto {
+            cargo_args.push(String::from("--config"));
+            cargo_args.push(format!("profile.release.lto=\"{lto}\""));
+        }
+
+        if args.all_features {
+            cargo_args.push(String::from("--all-features"));
+        } else if let Some(features) = &args.features {
+            cargo_args.push(format!("--features={}", features));
+        }
+        if args.no_default_features {
+            cargo_args.push(String::from("--no-default-features"));
+        }
+
+        // `build-std` needs an explicit target, the target's rustflags then apply to the standard library as well
+        // so `core` and `alloc` get stack sizes too
+        let spec = match &args.target_json {
+            Some(path) => Some(path.display().to_string()),
+            None => args.target.clone(),
+        };
+        if let Some(crates) = &args.build_std {
+            cargo_args.push(format!("-Zbuild-std={crates}"));
+            cargo_args.push(format!("--target={}", spec.as_deref().unwrap_or(target)));
+        } else if let Some(spec) = spec {
+            cargo_args.push(format!("--target={spec}"));
+        }
+        // recent nightlies only accept target specifications with this unstable flag
+        if args.target_json.is_some() {
+            cargo_args.push(String::from("-Zjson-target-spec"));
+        }
+
+        cargo_args.push(format!("--{kind}={file}"));
+
+        info!("Running: cargo {}", cargo_args.join(" "));
+        run_cargo(Command::new("cargo").args(&cargo_args), kind, file)
+    };
+
+    if let Some(tmp_file) = tmp_file {
+        std::fs::remove_file(tmp_file)?;
+    }
+
+    cargo_res
+}
+
+/// Splits a `--build-command` template at whitespace and fills in the placeholders
+///
+/// `{config}` is the `target.<TRIPLE>.rustflags=[...]` value for `cargo --config`, `{rustflags}` the same flags
+/// separated by spaces like `RUSTFLAGS` expects them and `{artifact}` selects the target, e.g. `--bin=app`.
+fn expand_template(
+    template: &str,
+    config: &str,
+    rustflags: &str,
+    kind: &str,
+    file: &str,
+) -> Vec<String> {
+    let artifact = format!("--{kind}={file}");
+    template
+        .split_whitespace()
+        .map(|token| {
+            token
+                .replace("{config}", config)
+                .replace("{rustflags}", rustflags)
+                .replace("{artifact}", &artifact)
+        })
+        .collect()
+}
+
+/// Runs a `--build-command`, leading `KEY=VALUE` words set environment variables like in a shell
+fn run_custom(
+    command: &[String],
+    kind: &str,
+    file: &str,
+) -> anyhow::Result<(ExitStatus, Option<PathBuf>)> {
+    let mut words = command.iter().peekable();
+    let mut env = Vec::new();
+    while let Some((key, value)) = words.peek().and_then(|w| w.split_once('=')) {
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            break;
+        }
+        env.push((key, value));
+        words.next();
+    }
+
+    let Some(program) = words.next() else {
+        bail!("The build command doesn't contain a program to run");
+    };
+    let mut command = Command::new(program);
+    command.args(words).envs(env);
+    run_cargo(&mut command, kind, file)
+}
+
+/// Runs cargo with JSON messages on stdout and picks the executable of the `kind` target `file` from them
+///
+/// Other output on stdout is passed through to stderr.
+fn run_cargo(
+    command: &mut Command,
+    kind: &str,
+    file: &str,
+) -> anyhow::Result<(ExitStatus, Option<PathBuf>)> {
+    let mut child = command.stdout(Stdio::piped()).spawn()?;
+
+    let mut executable = None;
+    for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+        let line = line?;
+        let Ok(message) = json::parse(&line) else {
+            eprintln!("{line}");
+            continue;
+        };
+
+        if message.get("reason").and_then(|r| r.as_str()) != Some("compiler-artifact") {
+            continue;
+        }
+
+        let Some(target) = message.get("target") else {
+            continue;
+        };
+        let is_kind = target
+            .get("kind")
+            .and_then(|k| k.as_array())
+            .map(|k| k.iter().any(|k| k.as_str() == Some(kind)))
+            .unwrap_or(false);
+        if !is_kind || target.get("name").and_then(|n| n.as_str()) != Some(file) {
+            continue;
+        }
+
+        if let Some(path) = message.get("executable").and_then(|e| e.as_str()) {
+            debug!("Cargo reported executable {path}");
+            executable = Some(PathBuf::from(path));
+        }
+    }
+
+    Ok((child.wait()?, executable))
+}
+
+/// The name cargo uses for `target` in config keys and the `target` directory
+///
+/// That's the triple itself or the file stem of a custom target specification like `thumbv7em-custom.json`.
+fn target_name(target: &str) -> &str {
+    if target.ends_with(".json") {
+        Path::new(target)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(target)
+    } else {
+        target
+    }
+}
+
+/// Resolves the path of the ELF produced by `build`
+fn artifact_path(
+    args: &Args,
+    project: &Project,
+    kind: &str,
+    file: &str,
+    target: &str,
+    host: &str,
+) -> anyhow::Result<PathBuf> {
+    let mut path: PathBuf = if let Some(binary) = &args.out_override {
+        binary.clone()
+    } else if kind == "example" {
+        project.path(
+            Artifact::Example(file),
+            Profile::Release,
+            Some(target),
+            host,
+        )?
+    } else {
+        project.path(Artifact::Bin(file), Profile::Release, Some(target), host)?
+    };
+
+    // the project crate seems to have problems with workspaces (at least on Windows) ... if the file isn't there let's guess one level up
+    // otherwise the user can still specify `out_override`
+    if !path.exists() {
+        let mut parts: Vec<Component> = path.components().collect();
+        let target_index = parts
+            .iter()
+            .position(|c| matches!(c, Component::Normal(name) if name.to_str() == Some("target")))
+            .unwrap_or(usize::MAX);
+
+        if target_index != usize::MAX && target_index > 0 {
+            parts.remove(target_index - 1);
+
+            let mut tmp = PathBuf::new();
+            for c in parts {
+                tmp.push(c);
+            }
+
+            path = tmp;
+        }
+    }
+
+    Ok(path)
+}
+
+/// Checks whether the linker kept the `.stack_sizes` section
+pub fn has_stack_sizes(elf: &[u8]) -> anyhow::Result<bool> {
+    analysis::check_plausible(elf)?;
+    let elf = ElfFile::new(elf).map_err(anyhow::Error::msg)?;
+    Ok(elf.find_section_by_name(".stack_sizes").is_some())
+}
@@ -0,0 +1,455 @@
+//! Stack frame sizes estimated from the call frame information (`.eh_frame`, `.debug_frame`) for `--from-cfi`
+//!
+//! The unwind tables describe where the canonical frame address (CFA) is relative to the stack pointer at every
+//! instruction. The offset grows with each push and stack adjustment in the prologue, so its maximum within a
+//! function minus the offset at its entry is the frame. That's an estimate: once the CFA is based on the frame
+//! pointer, later adjustments of the stack pointer aren't described anymore.
+
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    io::Cursor,
+};
+
+use byteorder::{ReadBytesExt, LE};
+use xmas_elf::{header, ElfFile};
+
+/// Pointer encodings of `.eh_frame`
+const DW_EH_PE_OMIT: u8 = 0xff;
+const DW_EH_PE_PCREL: u8 = 0x10;
+
+/// A common information entry, shared by the FDEs of a section
+struct Cie<'a> {
+    data_align: i64,
+    /// Encoding of the addresses in the FDEs, `.debug_frame` always uses plain addresses
+    fde_encoding: u8,
+    /// Whether the FDEs carry augmentation data to skip
+    augmented: bool,
+    initial_instructions: &'a [u8],
+}
+
+/// A section with call frame information
+struct FrameSection<'a> {
+    data: &'a [u8],
+    address: u64,
+    eh_frame: bool,
+    /// Size of an address in bytes
+    word: u8,
+}
+
+/// Returns the estimated frame size of every function described by the call frame information, keyed by the
+/// start address of the function
+///
+/// Relocatable objects have no usable addresses here, their FDEs refer to the functions through relocations.
+/// Malformed entries end the parsing of their section.
+pub fn frame_sizes(elf: &ElfFile) -> BTreeMap<u64, u64> {
+    if elf.header.pt2.type_().as_type() == header::Type::Relocatable {
+        return BTreeMap::new();
+    }
+
+    let word = match elf.header.pt1.class() {
+        header::Class::ThirtyTwo => 4,
+        _ => 8,
+    };
+    let mut sizes = BTreeMap::new();
+    for section in elf.section_iter() {
+        let eh_frame = match section.get_name(elf) {
+            Ok(".eh_frame") => true,
+            Ok(".debug_frame") => false,
+            _ => continue,
+        };
+        let section = FrameSection {
+            data: section.raw_data(elf),
+            address: section.address(),
+            eh_frame,
+            word,
+        };
+        // `.eh_frame` and `.debug_frame` usually describe the same functions, the first one wins
+        for (address, size) in section.frame_sizes() {
+            sizes.entry(address).or_insert(size);
+        }
+    }
+    sizes
+}
+
+impl<'a> FrameSection<'a> {
+    fn frame_sizes(&self) -> Vec<(u64, u64)> {
+        let mut sizes = Vec::new();
+        let mut cies = HashMap::new();
+        let mut offset = 0;
+        while let Some((id, id_position, body, end)) = self.entry(offset) {
+            let is_cie = if self.eh_frame {
+                id == 0
+            } else {
+                id == 0xffff_ffff || id == u64::MAX
+            };
+            if !is_cie {
+                // `.eh_frame` points back relative to the id field, `.debug_frame` gives the section offset
+                let cie_offset = if self.eh_frame {
+                    id_position.wrapping_sub(id as usize)
+                } else {
+                    id as usize
+                };
+                let cie = match cies.entry(cie_offset) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => match self.cie(cie_offset) {
+                        Some(cie) => entry.insert(cie),
+                        None => break,
+                    },
+                };
+                match self.fde(cie, body, end) {
+                    Some(Some(size)) => sizes.push(size),
+                    Some(None) => (),
+                    None => break,
+                }
+            }
+            offset = end;
+        }
+        sizes
+    }
+
+    /// Reads the header of the entry at `offset`, returns its CIE id or pointer, the offset of that field and
+    /// where the rest of the entry and the next entry start
+    fn entry(&self, offset: usize) -> Option<(u64, usize, usize, usize)> {
+        let mut cursor = Cursor::new(self.data.get(offset..)?);
+        let (length, dwarf64) = match cursor.read_u32::<LE>().ok()? {
+            // the terminator of `.eh_frame`
+            0 => return None,
+            0xffff_ffff => (cursor.read_u64::<LE>().ok()?.checked_add(12)?, true),
+            length => (u64::from(length) + 4, false),
+        };
+        let id_position = offset + cursor.position() as usize;
+        // `.eh_frame` keeps the CIE pointer at 4 bytes
+        let id = if dwarf64 && !self.eh_frame {
+            cursor.read_u64::<LE>().ok()?
+        } else {
+            u64::from(cursor.read_u32::<LE>().ok()?)
+        };
+        let end = offset.checked_add(usize::try_from(length).ok()?)?;
+        if end > self.data.len() {
+            return None;
+        }
+        Some((id, id_position, offset + cursor.position() as usize, end))
+    }
+
+    fn cie(&self, offset: usize) -> Option<Cie<'a>> {
+        let (_, _, body, end) = self.entry(offset)?;
+        let data = &self.data[..end];
+        let mut cursor = Cursor::new(data);
+        cursor.set_position(body as u64);
+
+        let version = cursor.read_u8().ok()?;
+        let mut augmentation = Vec::new();
+        loop {
+            match cursor.read_u8().ok()? {
+                0 => break,
+                c => augmentation.push(c),
+            }
+        }
+        let mut word = self.word;
+        if !self.eh_frame && version >= 4 {
+            word = cursor.read_u8().ok()?;
+            let _segment_size = cursor.read_u8().ok()?;
+        }
+        let _code_align = leb128::read::unsigned(&mut cursor).ok()?;
+        let data_align = leb128::read::signed(&mut cursor).ok()?;
+        if version == 1 {
+            cursor.read_u8().ok()?;
+        } else {
+            leb128::read::unsigned(&mut cursor).ok()?;
+        }
+
+        let mut fde_encoding = if word == 4 { 0x03 } else { 0x04 };
+        let augmented = augmentation.first() == Some(&b'z');
+        if augmented {
+            let length = leb128::read::unsigned(&mut cursor).ok()?;
+            let instructions = cursor.position().checked_add(length)?;
+            for c in &augmentation[1..] {
+                match c {
+                    b'R' => fde_encoding = cursor.read_u8().ok()?,
+                    b'P' => {
+                        let encoding = cursor.read_u8().ok()?;
+                        self.pointer(&mut cursor, encoding)?;
+                    }
+                    b'L' => {
+                        cursor.read_u8().ok()?;
+                    }
+                    _ => break,
+                }
+            }
+            cursor.set_position(instructions);
+        } else if !augmentation.is_empty() {
+            // unknown augmentations change the layout of the entries
+            return None;
+        }
+
+        Some(Cie {
+            data_align,
+            fde_encoding,
+            augmented,
+            initial_instructions: data.get(cursor.position() as usize..)?,
+        })
+    }
+
+    /// Returns the start address and the frame size of the function, `None` on malformed entries and
+    /// `Some(None)` for functions whose frame can't be told
+    fn fde(&self, cie: &Cie, body: usize, end: usize) -> Option<Option<(u64, u64)>> {
+        let mut cursor = Cursor::new(&self.data[..end]);
+        cursor.set_position(body as u64);
+        let start = self.pointer(&mut cursor, cie.fde_encoding)?;
+        let _range = self.pointer(&mut cursor, cie.fde_encoding & 0x0f)?;
+        if cie.augmented {
+            let length = leb128::read::unsigned(&mut cursor).ok()?;
+            cursor.set_position(cursor.position().checked_add(length)?);
+        }
+        let instructions = self.data.get(cursor.position() as usize..end)?;
+
+        let mut state = State::default();
+        if state.run(self, cie, cie.initial_instructions).is_none() {
+            return Some(None);
+        }
+        let entry = state.offset;
+        state.max = entry;
+        if state.run(self, cie, instructions).is_none() {
+            return Some(None);
+        }
+        // a zero start address is a discarded function
+        Some((start != 0).then_some((start, (state.max - entry).max(0) as u64)))
+    }
+
+    /// Reads an address in the `.eh_frame` pointer `encoding`
+    fn pointer(&self, cursor: &mut Cursor<&[u8]>, encoding: u8) -> Option<u64> {
+        if encoding == DW_EH_PE_OMIT {
+            return Some(0);
+        }
+        let field = self.address.wrapping_add(cursor.position());
+        let value = match encoding & 0x0f {
+            0x00 if self.word == 4 => u64::from(cursor.read_u32::<LE>().ok()?),
+            0x00 => cursor.read_u64::<LE>().ok()?,
+            0x01 => leb128::read::unsigned(cursor).ok()?,
+            0x02 => u64::from(cursor.read_u16::<LE>().ok()?),
+            0x03 => u64::from(cursor.read_u32::<LE>().ok()?),
+            0x04 => cursor.read_u64::<LE>().ok()?,
+            0x09 => leb128::read::signed(cursor).ok()? as u64,
+            0x0a => i64::from(cursor.read_i16::<LE>().ok()?) as u64,
+            0x0b => i64::from(cursor.read_i32::<LE>().ok()?) as u64,
+            0x0c => cursor.read_i64::<LE>().ok()? as u64,
+            _ => return None,
+        };
+        let value = match encoding & 0x70 {
+            0 => value,
+            DW_EH_PE_PCREL => field.wrapping_add(value),
+            // relative to the text or data segment, not used for the addresses of functions
+            _ => return None,
+        };
+        Some(if self.word == 4 {
+            value & 0xffff_ffff
+        } else {
+            value
+        })
+    }
+}
+
+/// The CFA offset while running the instructions of a CIE and an FDE
+#[derive(Default)]
+struct State {
+    offset: i64,
+    max: i64,
+    remembered: Vec<i64>,
+}
+
+impl State {
+    /// Runs the call frame instructions, `None` if they're unknown or malformed
+    fn run(&mut self, section: &FrameSection, cie: &Cie, instructions: &[u8]) -> Option<()> {
+        let mut cursor = Cursor::new(instructions);
+        let uleb = |cursor: &mut Cursor<&[u8]>| leb128::read::unsigned(cursor).ok();
+        let sleb = |cursor: &mut Cursor<&[u8]>| leb128::read::signed(cursor).ok();
+        let block = |cursor: &mut Cursor<&[u8]>| {
+            let length = leb128::read::unsigned(cursor).ok()?;
+            cursor.set_position(cursor.position().checked_add(length)?);
+            Some(())
+        };
+
+        while (cursor.position() as usize) < instructions.len() {
+            let op = cursor.read_u8().ok()?;
+            match op >> 6 {
+                // DW_CFA_advance_loc, DW_CFA_restore
+                1 | 3 => continue,
+                // DW_CFA_offset
+                2 => {
+                    uleb(&mut cursor)?;
+                    continue;
+                }
+                _ => (),
+            }
+            match op {
+                // DW_CFA_nop
+                0x00 => (),
+                // DW_CFA_set_loc
+                0x01 => {
+                    section.pointer(&mut cursor, cie.fde_encoding)?;
+                }
+                // DW_CFA_advance_loc1, 2 and 4
+                0x02 => {
+                    cursor.read_u8().ok()?;
+                }
+                0x03 => {
+                    cursor.read_u16::<LE>().ok()?;
+                }
+                0x04 => {
+                    cursor.read_u32::<LE>().ok()?;
+                }
+                // DW_CFA_offset_extended, DW_CFA_register, DW_CFA_val_offset,
+                // DW_CFA_GNU_negative_offset_extended
+                0x05 | 0x09 | 0x14 | 0x2f => {
+                    uleb(&mut cursor)?;
+                    uleb(&mut cursor)?;
+                }
+                // DW_CFA_restore_extended, DW_CFA_undefined, DW_CFA_same_value, DW_CFA_def_cfa_register,
+                // DW_CFA_GNU_args_size
+                0x06 | 0x07 | 0x08 | 0x0d | 0x2e => {
+                    uleb(&mut cursor)?;
+                }
+                // DW_CFA_remember_state, DW_CFA_restore_state
+                0x0a => self.remembered.push(self.offset),
+                0x0b => self.offset = self.remembered.pop()?,
+                // DW_CFA_def_cfa
+                0x0c => {
+                    uleb(&mut cursor)?;
+                    self.offset = i64::try_from(uleb(&mut cursor)?).ok()?;
+                }
+                // DW_CFA_def_cfa_offset
+                0x0e => self.offset = i64::try_from(uleb(&mut cursor)?).ok()?,
+                // DW_CFA_def_cfa_expression
+                0x0f => block(&mut cursor)?,
+                // DW_CFA_expression, DW_CFA_val_expression
+                0x10 | 0x16 => {
+                    uleb(&mut cursor)?;
+                    block(&mut cursor)?;
+                }
+                // DW_CFA_offset_extended_sf, DW_CFA_val_offset_sf
+                0x11 | 0x15 => {
+                    uleb(&mut cursor)?;
+                    sleb(&mut cursor)?;
+                }
+                // DW_CFA_def_cfa_sf
+                0x12 => {
+                    uleb(&mut cursor)?;
+                    self.offset = sleb(&mut cursor)?.checked_mul(cie.data_align)?;
+                }
+                // DW_CFA_def_cfa_offset_sf
+                0x13 => self.offset = sleb(&mut cursor)?.checked_mul(cie.data_align)?,
+                _ => return None,
+            }
+            self.max = self.max.max(self.offset);
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: u64 = 0x1000;
+
+    /// A CIE with the augmentation `zR`, FDE addresses are `DW_EH_PE_pcrel | DW_EH_PE_sdata4` and the CFA starts
+    /// 8 bytes above the stack pointer like on x86_64
+    fn cie() -> Vec<u8> {
+        let body = [
+            0, 0, 0, 0, // CIE id
+            1, b'z', b'R', 0, // version, augmentation
+            1, 0x78, 16, // code and data alignment, return address register
+            1, 0x1b, // augmentation data
+            0x0c, 7, 8, // DW_CFA_def_cfa rsp+8
+            0x90, 1, // DW_CFA_offset r16
+        ];
+        let mut entry = (body.len() as u32).to_le_bytes().to_vec();
+        entry.extend(body);
+        entry
+    }
+
+    /// Appends an FDE for the function at `start` to the `.eh_frame` data in `section`, the CIE is at offset 0
+    fn push_fde(section: &mut Vec<u8>, start: u64, instructions: &[u8]) {
+        let offset = section.len();
+        let id = (offset + 4) as u32;
+        let field = ADDRESS + offset as u64 + 8;
+        let mut body = id.to_le_bytes().to_vec();
+        body.extend((start.wrapping_sub(field) as i32).to_le_bytes());
+        body.extend(0x20i32.to_le_bytes());
+        body.push(0);
+        body.extend(instructions);
+        section.extend((body.len() as u32).to_le_bytes());
+        section.extend(body);
+    }
+
+    fn sizes(data: &[u8]) -> Vec<(u64, u64)> {
+        FrameSection {
+            data,
+            address: ADDRESS,
+            eh_frame: true,
+            word: 8,
+        }
+        .frame_sizes()
+    }
+
+    #[test]
+    fn reads_pc_relative_fdes() {
+        let mut section = cie();
+        // push rbp, sub rsp 48: the CFA moves from 8 to 16 and 64
+        push_fde(
+            &mut section,
+            0x2000,
+            &[0x41, 0x0e, 16, 0x86, 2, 0x44, 0x0e, 64],
+        );
+        // a leaf function without any adjustment
+        push_fde(&mut section, 0x2040, &[]);
+        section.extend([0, 0, 0, 0]);
+        assert_eq!(sizes(&section), [(0x2000, 56), (0x2040, 0)]);
+    }
+
+    #[test]
+    fn restores_remembered_states() {
+        let mut section = cie();
+        // an epilogue in the middle of the function, the code after it still has the full frame
+        let instructions = [
+            0x0e, 32, 0x0a, 0x0e, 8, 0x41, 0x0b, 0x41, 0x0e, 48, 0x41, 0x0e, 8,
+        ];
+        push_fde(&mut section, 0x2000, &instructions);
+        assert_eq!(sizes(&section), [(0x2000, 40)]);
+
+        let cie = FrameSection {
+            data: &section,
+            address: ADDRESS,
+            eh_frame: true,
+            word: 8,
+        };
+        let mut state = State::default();
+        assert!(state
+            .run(&cie, &cie.cie(0).unwrap(), &[0x0e, 32, 0x0a, 0x0e, 8, 0x0b])
+            .is_some());
+        assert_eq!(state.offset, 32);
+        // nothing to restore
+        assert!(State::default()
+            .run(&cie, &cie.cie(0).unwrap(), &[0x0b])
+            .is_none());
+    }
+
+    #[test]
+    fn stops_at_malformed_entries() {
+        let mut section = cie();
+        push_fde(&mut section, 0x2000, &[0x0e, 16]);
+        // an unknown instruction and a truncated operand leave the frames unknown
+        push_fde(&mut section, 0x2040, &[0x0e, 16, 0x3f]);
+        push_fde(&mut section, 0x2080, &[0x0e, 0x80]);
+        push_fde(&mut section, 0x20c0, &[0x0e, 24]);
+        // an entry claiming to be longer than the section ends the parsing
+        let truncated = section.len();
+        push_fde(&mut section, 0x2100, &[0x0e, 32]);
+        section[truncated..truncated + 4].copy_from_slice(&0x100u32.to_le_bytes());
+        push_fde(&mut section, 0x2140, &[0x0e, 32]);
+        assert_eq!(sizes(&section), [(0x2000, 8), (0x20c0, 16)]);
+
+        // a CIE cut short
+        assert_eq!(sizes(&cie()[..12]), []);
+    }
+}
@@ -0,0 +1,129 @@
+//! `--compare-features` and `--compare-toolchains`, building twice to see what a feature or compiler upgrade costs
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    cargo::{self, Build},
+    map::MapFile,
+    snapshot::{self, FunctionSize},
+    Args,
+};
+
+/// Builds the artifact without and with `feature` and prints the functions whose stack usage changed
+///
+/// A default `feature` is left out by building without the default features, with the other defaults given
+/// explicitly.
+pub fn features(args: &Args, feature: &str) -> anyhow::Result<()> {
+    let mut others: Vec<&str> = args
+        .features
+        .as_deref()
+        .unwrap_or_default()
+        .split([' ', ','])
+        .filter(|f| !f.is_empty() && *f != feature)
+        .collect();
+
+    let table = cargo::package_features()?;
+    let mut without = args.clone();
+    if !args.no_default_features && enables(&table, "default", feature) {
+        let defaults = table.get("default").map(Vec::as_slice).unwrap_or_default();
+        if let Some(other) = defaults
+            .iter()
+            .find(|d| *d != feature && enables(&table, d, feature))
+        {
+            bail!("`{feature}` is also enabled by the default feature `{other}`, compare that one instead");
+        }
+        without.no_default_features = true;
+        others.extend(
+            defaults
+                .iter()
+                .map(String::as_str)
+                .filter(|d| *d != feature),
+        );
+    }
+    without.features = Some(others.join(","));
+    let mut with = args.clone();
+    with.features = Some(
+        others
+            .iter()
+            .chain([&feature])
+            .copied()
+            .collect::<Vec<_>>()
+            .join(","),
+    );
+
+    eprintln!("Building without `{feature}`");
+    let baseline = function_sizes(args, &cargo::build_artifact(&without)?)?;
+    eprintln!("Building with `{feature}`");
+    let current = function_sizes(args, &cargo::build_artifact(&with)?)?;
+
+    println!("Stack impact of the feature `{feature}`:");
+    snapshot::print_function_diff(&current, &baseline, args.delta_threshold);
+    Ok(())
+}
+
+/// Whether the feature `from` enables `feature`, directly or through other features of the package
+fn enables(table: &BTreeMap<String, Vec<String>>, from: &str, feature: &str) -> bool {
+    let mut pending = vec![from];
+    let mut seen = BTreeSet::new();
+    while let Some(next) = pending.pop() {
+        if next == feature {
+            return true;
+        }
+        if seen.insert(next) {
+            pending.extend(table.get(next).into_iter().flatten().map(String::as_str));
+        }
+    }
+    false
+}
+
+/// Builds the artifact with the rustup toolchains `a` and `b` and prints the functions whose stack usage changed
+pub fn toolchains(args: &Args, a: &str, b: &str) -> anyhow::Result<()> {
+    // fail before spending the time on the first build
+    for toolchain in [a, b] {
+        with_toolchain(toolchain, || Ok(rustc_version::version_meta()?)).with_context(|| {
+            format!("The toolchain `{toolchain}` isn't usable, install it with `rustup toolchain install {toolchain}`")
+        })?;
+    }
+
+    eprintln!("Building with `{a}`");
+    let baseline = with_toolchain(a, || function_sizes(args, &cargo::build_artifact(args)?))?;
+    eprintln!("Building with `{b}`");
+    let current = with_toolchain(b, || function_sizes(args, &cargo::build_artifact(args)?))?;
+
+    println!("Stack impact of switching from `{a}` to `{b}`:");
+    snapshot::print_function_diff(&current, &baseline, args.delta_threshold);
+    Ok(())
+}
+
+/// Runs `f` with `RUSTUP_TOOLCHAIN` set like `cargo +toolchain` does, restoring the previous value afterwards
+fn with_toolchain<T>(toolchain: &str, f: impl FnOnce() -> anyhow::Result<T>) -> anyhow::Result<T> {
+    let previous = env::var_os("RUSTUP_TOOLCHAIN");
+    env::set_var("RUSTUP_TOOLCHAIN", toolchain);
+    let result = f();
+    match previous {
+        Some(previous) => env::set_var("RUSTUP_TOOLCHAIN", previous),
+        None => env::remove_var("RUSTUP_TOOLCHAIN"),
+    }
+    result
+}
+
+/// Analyzes the build like the report does, with the same alignment and filters applied to the stack sizes
+pub fn function_sizes(args: &Args, build: &Build) -> anyhow::Result<Vec<FunctionSize>> {
+    let map = match &args.map {
+        Some(path) => MapFile::load(path)?,
+        None => MapFile::default(),
+    };
+    let functions = crate::analyze(args, &build.path, &build.elf, &map)?;
+    Ok(
+        crate::processed_rows(args, &build.path, &build.elf, &functions)?
+            .iter()
+            .filter(|row| !row.alias)
+            .map(FunctionSize::of)
+            .collect(),
+    )
+}
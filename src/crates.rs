@@ -0,0 +1,168 @@
+use std::{collections::HashSet, path::Path, process::Command};
+
+use anyhow::bail;
+
+use crate::json;
+
+/// Crates shipped with the toolchain
+pub const STD_CRATES: &[&str] = &[
+    "std",
+    "core",
+    "alloc",
+    "compiler_builtins",
+    "panic_abort",
+    "panic_unwind",
+    "proc_macro",
+    "std_detect",
+    "rustc_std_workspace_core",
+    "rustc_std_workspace_alloc",
+];
+
+/// Returns the crate a demangled function name belongs to
+///
+/// For trait impls (`<foo::Bar as core::fmt::Debug>::fmt`) that's the crate of the implementing type.
+/// Names without a path like C functions have no crate.
+pub fn crate_of(name: &str) -> Option<&str> {
+    let path = path_of(name);
+    let end = path.find("::")?;
+    let krate = &path[..end];
+    // crate disambiguator of v0 mangled names
+    let krate = krate.split_once('[').map(|(k, _)| k).unwrap_or(krate);
+    is_identifier(krate).then_some(krate)
+}
+
+/// Returns the top level module of the crate a demangled function name belongs to, see [`crate_of`]
+///
+/// Functions at the root of their crate have no module.
+pub fn module_of(name: &str) -> Option<&str> {
+    crate_of(name)?;
+    let path = path_of(name);
+    let rest = &path[path.find("::")? + 2..];
+    let end = rest.find("::")?;
+    let module = &rest[..end];
+    is_identifier(module).then_some(module)
+}
+
+/// Skips the leading `<`, references, pointers and keywords in front of the path of a name
+fn path_of(name: &str) -> &str {
+    let mut name = name;
+    loop {
+        name = name.trim_start_matches(['<', '&', '*', '(', '[']);
+        match ["mut ", "const ", "dyn ", "impl "]
+            .iter()
+            .find_map(|keyword| name.strip_prefix(keyword))
+        {
+            Some(rest) => name = rest,
+            None => return name,
+        }
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Crates of the workspace members and of their dependencies, as queried from `cargo metadata`
+#[derive(Debug, Default)]
+pub struct Crates {
+    pub local: HashSet<String>,
+    pub dependencies: HashSet<String>,
+    /// Set when there was no `cargo metadata` to list the dependencies, every other crate counts as one
+    pub unlisted: bool,
+}
+
+impl Crates {
+    /// `flags` are passed to `cargo metadata`, e.g. `--offline`, a `platform` leaves out the dependencies
+    /// of other targets
+    pub fn query(flags: &[&str], platform: Option<&str>) -> anyhow::Result<Crates> {
+        let mut command = Command::new("cargo");
+        command.args(["metadata", "--format-version=1"]).args(flags);
+        if let Some(platform) = platform {
+            command.args(["--filter-platform", platform]);
+        }
+        let output = command.output()?;
+        if !output.status.success() {
+            bail!(
+                "`cargo metadata` failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let metadata = json::parse(std::str::from_utf8(&output.stdout)?)?;
+        let members: Vec<&str> = metadata
+            .get("workspace_members")
+            .and_then(|m| m.as_array())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|m| m.as_str())
+            .collect();
+
+        let mut crates = Crates::default();
+        for package in metadata
+            .get("packages")
+            .and_then(|p| p.as_array())
+            .unwrap_or_default()
+        {
+            let local = package
+                .get("id")
+                .and_then(|id| id.as_str())
+                .map(|id| members.contains(&id))
+                .unwrap_or(false);
+            let targets = package
+                .get("targets")
+                .and_then(|t| t.as_array())
+                .unwrap_or_default();
+            for name in targets
+                .iter()
+                .filter_map(|t| t.get("name").and_then(|n| n.as_str()))
+            {
+                let name = name.replace('-', "_");
+                if local {
+                    crates.local.insert(name);
+                } else {
+                    crates.dependencies.insert(name);
+                }
+            }
+        }
+        Ok(crates)
+    }
+
+    /// Crates of ELF files analyzed outside of a cargo project
+    ///
+    /// The file names stand in for the workspace crates, e.g. `my-app` for `my_app`, everything else that isn't
+    /// part of the standard library is taken as a dependency.
+    pub fn from_files(paths: &[impl AsRef<Path>]) -> Crates {
+        Crates {
+            local: paths
+                .iter()
+                .filter_map(|path| path.as_ref().file_stem()?.to_str())
+                .map(|stem| stem.replace('-', "_"))
+                .collect(),
+            dependencies: HashSet::new(),
+            unlisted: true,
+        }
+    }
+
+    /// Whether `krate` is a dependency rather than part of the workspace or the standard library
+    ///
+    /// Crates the standard library depends on aren't dependencies unless the workspace uses them too.
+    pub fn is_dependency(&self, krate: &str) -> bool {
+        (self.unlisted || self.dependencies.contains(krate))
+            && !self.local.contains(krate)
+            && !STD_CRATES.contains(&krate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_stand_in_for_the_workspace() {
+        let crates = Crates::from_files(&["target/release/my-app", "libother.so"]);
+        assert!(!crates.is_dependency("my_app"));
+        assert!(!crates.is_dependency("core"));
+        assert!(crates.is_dependency("serde"));
+        assert!(!crates.is_dependency("libother"));
+    }
+}
@@ -0,0 +1,271 @@
+use std::{env, path::Path, process::Command};
+
+use anyhow::{bail, Context};
+use cargo_project::Project;
+use rustc_version::Channel;
+
+use crate::{
+    analysis, cargo,
+    linker::{self, Dialect},
+    Args,
+};
+
+/// Checks the setup needed to get stack sizes, printing a hint for everything that's missing
+pub fn run(args: &Args) -> anyhow::Result<()> {
+    let mut failed = 0;
+    let mut report = |name: &str, result: Result<String, (String, &str)>| match result {
+        Ok(detail) => println!("[ ok ] {name}: {detail}"),
+        Err((detail, hint)) => {
+            failed += 1;
+            println!("[FAIL] {name}: {detail}");
+            println!("       hint: {hint}");
+        }
+    };
+
+    let meta = rustc_version::version_meta()?;
+    report(
+        "Toolchain",
+        if meta.channel == Channel::Nightly {
+            Ok(meta.short_version_string.clone())
+        } else {
+            Err((
+                meta.short_version_string.clone(),
+                "`-Z emit-stack-sizes` needs a nightly toolchain, e.g. add a `rust-toolchain` file containing `nightly`",
+            ))
+        },
+    );
+
+    let (_, compiler) = compiler();
+    report("Compiler", Ok(compiler));
+
+    let host = meta.host;
+    let target = target(args, &host);
+
+    let sysroot = rustc(&["--print", "sysroot"])?;
+    let installed = Path::new(sysroot.trim())
+        .join("lib/rustlib")
+        .join(&target)
+        .exists();
+    report(
+        "Target",
+        if installed {
+            Ok(target.clone())
+        } else {
+            Err((
+                format!("{target} is not installed"),
+                "install it with `rustup target add <TARGET>`, or build the standard library using `-Z build-std`",
+            ))
+        },
+    );
+
+    if args.build_std.is_some() {
+        let library = Path::new(sysroot.trim()).join("lib/rustlib/src/rust/library");
+        report(
+            "Standard library source",
+            if library.exists() {
+                Ok(library.display().to_string())
+            } else {
+                Err((
+                    "rust-src is not installed".to_string(),
+                    "`--build-std` needs it, install it with `rustup component add rust-src`",
+                ))
+            },
+        );
+    }
+
+    let tmp = env::temp_dir().join("ssize-doctor");
+    std::fs::create_dir_all(&tmp)?;
+    let stack_sizes = probe_stack_sizes(&tmp, &target);
+    let emits_stack_sizes = stack_sizes.is_ok();
+    report("Stack sizes", stack_sizes);
+
+    if !emits_stack_sizes {
+        println!("[skip] Linker script: needs stack size information to check");
+    } else if target == host {
+        report("Linker script", probe_linker_script(&tmp, &target));
+    } else {
+        println!("[skip] Linker script: probing it needs a runnable program for {target}");
+    }
+
+    std::fs::remove_dir_all(&tmp)?;
+
+    if failed > 0 {
+        bail!("{failed} checks failed");
+    }
+    Ok(())
+}
+
+/// Compiles the probe with the rustc cargo would use before building, failing if it has no stack sizes
+pub fn verify_rustc(args: &Args) -> anyhow::Result<()> {
+    let (_, compiler) = compiler();
+    let host = rustc_version::version_meta()
+        .with_context(|| format!("Failed to run `{compiler}`"))?
+        .host;
+    let target = target(args, &host);
+    let tmp = env::temp_dir().join("ssize-verify-rustc");
+    std::fs::create_dir_all(&tmp)?;
+    let result = probe_stack_sizes(&tmp, &target);
+    std::fs::remove_dir_all(&tmp)?;
+
+    if let Err((detail, hint)) = result {
+        let hint = if env::var_os("RUSTC_WRAPPER").is_some_and(|w| !w.is_empty()) {
+            "the `RUSTC_WRAPPER` might not pass on `-Z` flags, try without it"
+        } else {
+            hint
+        };
+        bail!("`{compiler}` doesn't emit stack sizes for {target}: {detail}\nhint: {hint}");
+    }
+    Ok(())
+}
+
+/// The target the build is for, a custom target as the path of its specification
+fn target(args: &Args, host: &str) -> String {
+    if let Some(spec) = &args.target_json {
+        return spec.display().to_string();
+    }
+    let project = Project::query(env::current_dir().unwrap_or_default()).ok();
+    args.target
+        .as_deref()
+        .or(project.as_ref().and_then(|p| p.target()))
+        .unwrap_or(host)
+        .to_string()
+}
+
+/// The compiler cargo runs and its description: `RUSTC` or `rustc`, behind `RUSTC_WRAPPER` if that's set
+///
+/// Wrappers like `sccache` might not pass on `-Z` flags, so the probe runs through them as well.
+fn compiler() -> (Command, String) {
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    match env::var_os("RUSTC_WRAPPER").filter(|wrapper| !wrapper.is_empty()) {
+        Some(wrapper) => {
+            let description = format!("{} {}", wrapper.to_string_lossy(), rustc.to_string_lossy());
+            let mut command = Command::new(wrapper);
+            command.arg(rustc);
+            (command, description)
+        }
+        None => {
+            let description = rustc.to_string_lossy().into_owned();
+            (Command::new(rustc), description)
+        }
+    }
+}
+
+/// Compiles a library with `-Z emit-stack-sizes` and looks for the section in the object file
+fn probe_stack_sizes(tmp: &Path, target: &str) -> Result<String, (String, &'static str)> {
+    let source = tmp.join("probe.rs");
+    let object = tmp.join("probe.o");
+    std::fs::write(
+        &source,
+        "#![no_std]\npub fn probe(x: u32) -> u32 { x.wrapping_mul(3) }\n",
+    )
+    .map_err(|err| {
+        (
+            err.to_string(),
+            "make sure the temporary directory is writable",
+        )
+    })?;
+
+    let output = compiler()
+        .0
+        .args(["--crate-type=lib", "--emit=obj", "-Z", "emit-stack-sizes"])
+        .arg(format!("--target={target}"))
+        .arg("-o")
+        .arg(&object)
+        .arg(&source)
+        .output()
+        .map_err(|err| {
+            (
+                err.to_string(),
+                "make sure `rustc` is in the PATH, or `RUSTC` points to it",
+            )
+        })?;
+    if !output.status.success() {
+        return Err((
+            String::from_utf8_lossy(&output.stderr)
+                .lines()
+                .next()
+                .unwrap_or("compiling the probe failed")
+                .to_string(),
+            "use a nightly toolchain which has the target installed",
+        ));
+    }
+
+    let elf = std::fs::read(&object).map_err(|err| (err.to_string(), "check the output above"))?;
+    match analysis::analyze_executable(&elf, true) {
+        Ok(functions) if functions.matched > 0 => {
+            Ok("`-Z emit-stack-sizes` is accepted".to_string())
+        }
+        Ok(_) => Err((
+            "the probe has no `.stack_sizes` section".to_string(),
+            "the code generator of this target might not support stack size information",
+        )),
+        Err(err) => Err((err.to_string(), "please report this as a bug of ssize")),
+    }
+}
+
+/// Links a program with the injected linker script and checks that `.stack_sizes` is kept
+fn probe_linker_script(tmp: &Path, target: &str) -> Result<String, (String, &'static str)> {
+    let source = tmp.join("main.rs");
+    std::fs::write(&source, "fn main() {}\n").map_err(|err| {
+        (
+            err.to_string(),
+            "make sure the temporary directory is writable",
+        )
+    })?;
+
+    let config = cargo::config().ok().flatten();
+    let rustflags = cargo::effective_rustflags(config.as_ref(), target)
+        .map(|(flags, _)| flags)
+        .unwrap_or_default();
+    let dialects = match linker::detect(config.as_ref(), target, &rustflags) {
+        Some(dialect) => vec![dialect],
+        None => vec![Dialect::Gnu, Dialect::Lld],
+    };
+
+    let mut errors = Vec::new();
+    for dialect in dialects {
+        std::fs::write(tmp.join(dialect.file_name(None)), dialect.script(None)).map_err(|err| {
+            (
+                err.to_string(),
+                "make sure the temporary directory is writable",
+            )
+        })?;
+
+        let binary = tmp.join("probe");
+        let output = compiler()
+            .0
+            .args(["-Z", "emit-stack-sizes", "-C"])
+            .arg(format!("link-arg=-T{}", dialect.file_name(None)))
+            .arg("-C")
+            .arg(format!("link-arg=-L{}", tmp.display()))
+            .arg("-o")
+            .arg(&binary)
+            .arg(&source)
+            .output()
+            .map_err(|err| (err.to_string(), "make sure `rustc` is in the PATH"))?;
+        if !output.status.success() {
+            errors.push(format!("{dialect} script: linking failed"));
+            continue;
+        }
+
+        match std::fs::read(&binary).map(|elf| cargo::has_stack_sizes(&elf)) {
+            Ok(Ok(true)) => return Ok(format!("the {dialect} script keeps `.stack_sizes`")),
+            _ => errors.push(format!("{dialect} script: no `.stack_sizes` section")),
+        }
+    }
+
+    Err((
+        errors.join(", "),
+        "configure the linker in `.cargo/config.toml` (`target.<TRIPLE>.linker`) so the right script dialect is used, \
+         or try other section attributes with `--section-attrs`",
+    ))
+}
+
+/// Runs `rustc` and returns its stdout
+fn rustc(args: &[&str]) -> anyhow::Result<String> {
+    let output = compiler().0.args(args).output()?;
+    if !output.status.success() {
+        bail!("`rustc {}` failed", args.join(" "));
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
@@ -0,0 +1,629 @@
+//! Function names from the debug information (`.debug_info`) for `--names-from-debug`
+//!
+//! Only what's needed to name functions is decoded: the subprogram entries of every unit with their start
+//! address (`DW_AT_low_pc`), size and name. A definition without a name of its own is named after the entry its
+//! `DW_AT_specification` or `DW_AT_abstract_origin` refers to, that's how out-of-line copies of inlined
+//! functions are described. DWARF 2 to 5 are supported, type units, split DWARF and compressed sections aren't.
+
+use std::{
+    collections::{hash_map, HashMap},
+    io::Cursor,
+};
+
+use byteorder::{ReadBytesExt, LE};
+use xmas_elf::{header, ElfFile};
+
+const DW_TAG_SUBPROGRAM: u64 = 0x2e;
+
+const DW_AT_NAME: u64 = 0x03;
+const DW_AT_LOW_PC: u64 = 0x11;
+const DW_AT_HIGH_PC: u64 = 0x12;
+const DW_AT_ABSTRACT_ORIGIN: u64 = 0x31;
+const DW_AT_SPECIFICATION: u64 = 0x47;
+const DW_AT_LINKAGE_NAME: u64 = 0x6e;
+const DW_AT_STR_OFFSETS_BASE: u64 = 0x72;
+const DW_AT_ADDR_BASE: u64 = 0x73;
+const DW_AT_MIPS_LINKAGE_NAME: u64 = 0x2007;
+
+/// `SHF_COMPRESSED`, the section data starts with a compression header
+const SHF_COMPRESSED: u64 = 0x800;
+
+/// A function described by the debug information
+#[derive(Debug, PartialEq)]
+pub struct Subprogram<'a> {
+    pub address: u64,
+    /// Size of the code, `None` if there's no `DW_AT_high_pc`
+    pub size: Option<u64>,
+    /// The linkage (mangled) name if there is one, the plain name otherwise
+    pub name: &'a str,
+}
+
+/// Returns the functions of the debug information in the order of `.debug_info`
+///
+/// Relocatable objects have no usable addresses here, like for the call frame information. Malformed units are
+/// skipped.
+pub fn subprograms<'a>(elf: &ElfFile<'a>) -> Vec<Subprogram<'a>> {
+    if elf.header.pt2.type_().as_type() == header::Type::Relocatable {
+        return Vec::new();
+    }
+
+    let mut sections = Sections::default();
+    for section in elf.section_iter() {
+        if section.flags() & SHF_COMPRESSED != 0 {
+            continue;
+        }
+        let data = match section.get_name(elf) {
+            Ok(".debug_info") => &mut sections.info,
+            Ok(".debug_abbrev") => &mut sections.abbrev,
+            Ok(".debug_str") => &mut sections.str,
+            Ok(".debug_line_str") => &mut sections.line_str,
+            Ok(".debug_str_offsets") => &mut sections.str_offsets,
+            Ok(".debug_addr") => &mut sections.addr,
+            _ => continue,
+        };
+        *data = section.raw_data(elf);
+    }
+    sections.subprograms()
+}
+
+/// The debug sections the names are read from, missing ones are empty
+#[derive(Default)]
+struct Sections<'a> {
+    info: &'a [u8],
+    abbrev: &'a [u8],
+    str: &'a [u8],
+    line_str: &'a [u8],
+    str_offsets: &'a [u8],
+    addr: &'a [u8],
+}
+
+/// An abbreviation declaration, the layout of the entries using its code
+struct Abbreviation {
+    tag: u64,
+    /// Name, form and the value of `DW_FORM_implicit_const` of every attribute
+    attributes: Vec<(u64, u64, i64)>,
+}
+
+/// The header of a unit and the bases of its indexed forms
+struct Unit {
+    /// Offset of the unit in `.debug_info`, references within the unit are relative to it
+    start: u64,
+    version: u16,
+    /// 4 bytes for 32-bit DWARF, 8 for 64-bit DWARF
+    offset_size: u8,
+    address_size: u8,
+    str_offsets_base: u64,
+    addr_base: u64,
+}
+
+/// An attribute value as far as it matters for naming functions
+#[derive(Clone, Copy)]
+enum Value<'a> {
+    Address(u64),
+    AddressIndex(u64),
+    Constant(u64),
+    String(&'a str),
+    /// Offset into `.debug_str`
+    Strp(u64),
+    /// Offset into `.debug_line_str`
+    LineStrp(u64),
+    StringIndex(u64),
+    /// `.debug_info` offset of the referenced entry
+    Reference(u64),
+    Other,
+}
+
+/// The name and the referenced declaration of a subprogram entry
+struct Entry<'a> {
+    name: Option<&'a str>,
+    reference: Option<u64>,
+}
+
+impl<'a> Sections<'a> {
+    fn subprograms(&self) -> Vec<Subprogram<'a>> {
+        let mut entries = HashMap::new();
+        let mut definitions = Vec::new();
+        let mut abbreviations = HashMap::new();
+        let mut offset = 0;
+        while let Some(next) = self.unit(offset, &mut abbreviations, &mut entries, &mut definitions)
+        {
+            offset = next;
+        }
+
+        definitions
+            .into_iter()
+            .filter_map(|(address, size, entry)| {
+                let mut entry = &entry;
+                // the references only go from a definition to an abstract instance to a declaration
+                for _ in 0..4 {
+                    if let Some(name) = entry.name {
+                        return Some(Subprogram {
+                            address,
+                            size,
+                            name,
+                        });
+                    }
+                    entry = entries.get(&entry.reference?)?;
+                }
+                None
+            })
+            .collect()
+    }
+
+    /// Reads the subprograms of the unit at `offset`, returns where the next unit starts
+    ///
+    /// `entries` collects the subprograms by their offset, `definitions` the ones with an address.
+    fn unit(
+        &self,
+        offset: u64,
+        abbreviations: &mut HashMap<u64, HashMap<u64, Abbreviation>>,
+        entries: &mut HashMap<u64, Entry<'a>>,
+        definitions: &mut Vec<(u64, Option<u64>, Entry<'a>)>,
+    ) -> Option<u64> {
+        let mut cursor = Cursor::new(self.info);
+        cursor.set_position(offset);
+        let (length, offset_size) = match cursor.read_u32::<LE>().ok()? {
+            0xffff_ffff => (cursor.read_u64::<LE>().ok()?, 8),
+            length => (u64::from(length), 4),
+        };
+        let end = cursor.position().checked_add(length)?;
+        if end > self.info.len() as u64 {
+            return None;
+        }
+        let mut unit = Unit {
+            start: offset,
+            version: cursor.read_u16::<LE>().ok()?,
+            offset_size,
+            address_size: 0,
+            str_offsets_base: 0,
+            addr_base: 0,
+        };
+
+        let abbrev_offset;
+        if unit.version >= 5 {
+            let unit_type = cursor.read_u8().ok()?;
+            unit.address_size = cursor.read_u8().ok()?;
+            abbrev_offset = read_offset(&mut cursor, offset_size)?;
+            // only compile and partial units describe functions
+            if unit_type != 0x01 && unit_type != 0x03 {
+                return Some(end);
+            }
+        } else if unit.version >= 2 {
+            abbrev_offset = read_offset(&mut cursor, offset_size)?;
+            unit.address_size = cursor.read_u8().ok()?;
+        } else {
+            return Some(end);
+        }
+
+        let abbreviations = match abbreviations.entry(abbrev_offset) {
+            hash_map::Entry::Occupied(table) => table.into_mut(),
+            hash_map::Entry::Vacant(table) => match self.abbreviations(abbrev_offset) {
+                Some(abbreviations) => table.insert(abbreviations),
+                None => return Some(end),
+            },
+        };
+
+        let entries_start = cursor.position();
+        let mut cursor = Cursor::new(&self.info[..end as usize]);
+        cursor.set_position(entries_start);
+        // a malformed entry leaves nothing to resynchronize on, the rest of the unit is lost
+        let _ = self.entries(&mut unit, &mut cursor, abbreviations, entries, definitions);
+        Some(end)
+    }
+
+    fn entries(
+        &self,
+        unit: &mut Unit,
+        cursor: &mut Cursor<&'a [u8]>,
+        abbreviations: &HashMap<u64, Abbreviation>,
+        entries: &mut HashMap<u64, Entry<'a>>,
+        definitions: &mut Vec<(u64, Option<u64>, Entry<'a>)>,
+    ) -> Option<()> {
+        let end = cursor.get_ref().len() as u64;
+        while cursor.position() < end {
+            let offset = cursor.position();
+            let code = leb128::read::unsigned(cursor).ok()?;
+            // the end of a list of children
+            if code == 0 {
+                continue;
+            }
+            let abbreviation = abbreviations.get(&code)?;
+
+            let (mut name, mut linkage_name, mut low_pc, mut high_pc, mut reference) =
+                (None, None, None, None, None);
+            for &(attribute, form, implicit) in &abbreviation.attributes {
+                let value = self.value(unit, cursor, form, implicit)?;
+                match (attribute, value) {
+                    (DW_AT_NAME, _) => name = Some(value),
+                    (DW_AT_LINKAGE_NAME | DW_AT_MIPS_LINKAGE_NAME, _) => linkage_name = Some(value),
+                    (DW_AT_LOW_PC, _) => low_pc = Some(value),
+                    (DW_AT_HIGH_PC, _) => high_pc = Some(value),
+                    (DW_AT_SPECIFICATION | DW_AT_ABSTRACT_ORIGIN, Value::Reference(to)) => {
+                        reference = Some(to)
+                    }
+                    (DW_AT_STR_OFFSETS_BASE, Value::Constant(base)) => unit.str_offsets_base = base,
+                    (DW_AT_ADDR_BASE, Value::Constant(base)) => unit.addr_base = base,
+                    _ => (),
+                }
+            }
+            if abbreviation.tag != DW_TAG_SUBPROGRAM {
+                continue;
+            }
+
+            let name = linkage_name
+                .or(name)
+                .and_then(|name| self.string(unit, name))
+                .filter(|name| !name.is_empty());
+            let low_pc = low_pc.and_then(|low_pc| self.address(unit, low_pc));
+            match low_pc {
+                // a zero address is a function discarded by the linker
+                Some(address) if address != 0 => {
+                    let size = match high_pc.map(|high_pc| (high_pc, self.address(unit, high_pc))) {
+                        Some((Value::Constant(size), _)) => Some(size),
+                        Some((_, Some(high_pc))) => high_pc.checked_sub(address),
+                        _ => None,
+                    };
+                    definitions.push((address, size, Entry { name, reference }));
+                }
+                _ => {
+                    entries.insert(offset, Entry { name, reference });
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Reads the abbreviation declarations at `offset` of `.debug_abbrev`, keyed by their code
+    fn abbreviations(&self, offset: u64) -> Option<HashMap<u64, Abbreviation>> {
+        let mut cursor = Cursor::new(self.abbrev);
+        cursor.set_position(offset);
+        let mut abbreviations = HashMap::new();
+        loop {
+            let code = leb128::read::unsigned(&mut cursor).ok()?;
+            if code == 0 {
+                return Some(abbreviations);
+            }
+            let tag = leb128::read::unsigned(&mut cursor).ok()?;
+            let _children = cursor.read_u8().ok()?;
+            let mut attributes = Vec::new();
+            loop {
+                let name = leb128::read::unsigned(&mut cursor).ok()?;
+                let form = leb128::read::unsigned(&mut cursor).ok()?;
+                if name == 0 && form == 0 {
+                    break;
+                }
+                // DW_FORM_implicit_const keeps its value in the declaration
+                let implicit = if form == 0x21 {
+                    leb128::read::signed(&mut cursor).ok()?
+                } else {
+                    0
+                };
+                attributes.push((name, form, implicit));
+            }
+            abbreviations.insert(code, Abbreviation { tag, attributes });
+        }
+    }
+
+    /// Reads a value of the attribute `form`, only the classes needed for names and addresses are kept
+    fn value(
+        &self,
+        unit: &Unit,
+        cursor: &mut Cursor<&'a [u8]>,
+        form: u64,
+        implicit: i64,
+    ) -> Option<Value<'a>> {
+        let uleb = |cursor: &mut Cursor<&'a [u8]>| leb128::read::unsigned(cursor).ok();
+        let skip = |cursor: &mut Cursor<&'a [u8]>, length: u64| {
+            cursor.set_position(cursor.position().checked_add(length)?);
+            (cursor.position() <= cursor.get_ref().len() as u64).then_some(Value::Other)
+        };
+        let reference = |to: u64| Some(Value::Reference(unit.start.checked_add(to)?));
+
+        Some(match form {
+            // DW_FORM_addr
+            0x01 => Value::Address(read_sized(cursor, unit.address_size)?),
+            // DW_FORM_block2, DW_FORM_block4, DW_FORM_block, DW_FORM_block1, DW_FORM_exprloc
+            0x03 => {
+                let length = cursor.read_u16::<LE>().ok()?;
+                skip(cursor, u64::from(length))?
+            }
+            0x04 => {
+                let length = cursor.read_u32::<LE>().ok()?;
+                skip(cursor, u64::from(length))?
+            }
+            0x09 | 0x18 => {
+                let length = uleb(cursor)?;
+                skip(cursor, length)?
+            }
+            0x0a => {
+                let length = cursor.read_u8().ok()?;
+                skip(cursor, u64::from(length))?
+            }
+            // DW_FORM_data1, 2, 4 and 8
+            0x0b => Value::Constant(u64::from(cursor.read_u8().ok()?)),
+            0x05 => Value::Constant(u64::from(cursor.read_u16::<LE>().ok()?)),
+            0x06 => Value::Constant(u64::from(cursor.read_u32::<LE>().ok()?)),
+            0x07 => Value::Constant(cursor.read_u64::<LE>().ok()?),
+            // DW_FORM_string
+            0x08 => {
+                let string = c_string(cursor.get_ref(), cursor.position())?;
+                cursor.set_position(cursor.position() + string.len() as u64 + 1);
+                Value::String(string)
+            }
+            // DW_FORM_flag
+            0x0c => skip(cursor, 1)?,
+            // DW_FORM_sdata
+            0x0d => Value::Constant(leb128::read::signed(cursor).ok()? as u64),
+            // DW_FORM_strp
+            0x0e => Value::Strp(read_offset(cursor, unit.offset_size)?),
+            // DW_FORM_udata
+            0x0f => Value::Constant(uleb(cursor)?),
+            // DW_FORM_ref_addr, an address sized offset in DWARF 2
+            0x10 if unit.version == 2 => Value::Reference(read_sized(cursor, unit.address_size)?),
+            0x10 => Value::Reference(read_offset(cursor, unit.offset_size)?),
+            // DW_FORM_ref1, 2, 4, 8 and DW_FORM_ref_udata
+            0x11 => reference(u64::from(cursor.read_u8().ok()?))?,
+            0x12 => reference(u64::from(cursor.read_u16::<LE>().ok()?))?,
+            0x13 => reference(u64::from(cursor.read_u32::<LE>().ok()?))?,
+            0x14 => reference(cursor.read_u64::<LE>().ok()?)?,
+            0x15 => reference(uleb(cursor)?)?,
+            // DW_FORM_indirect
+            0x16 => {
+                let form = uleb(cursor)?;
+                let implicit = if form == 0x21 {
+                    leb128::read::signed(cursor).ok()?
+                } else {
+                    0
+                };
+                self.value(unit, cursor, form, implicit)?
+            }
+            // DW_FORM_sec_offset
+            0x17 => Value::Constant(read_offset(cursor, unit.offset_size)?),
+            // DW_FORM_flag_present
+            0x19 => Value::Other,
+            // DW_FORM_strx, DW_FORM_GNU_str_index
+            0x1a | 0x1f02 => Value::StringIndex(uleb(cursor)?),
+            // DW_FORM_addrx, DW_FORM_GNU_addr_index
+            0x1b | 0x1f01 => Value::AddressIndex(uleb(cursor)?),
+            // DW_FORM_ref_sup4, DW_FORM_ref_sig8, DW_FORM_ref_sup8, DW_FORM_data16
+            0x1c => skip(cursor, 4)?,
+            0x20 | 0x24 => skip(cursor, 8)?,
+            0x1e => skip(cursor, 16)?,
+            // DW_FORM_strp_sup, DW_FORM_GNU_ref_alt and DW_FORM_GNU_strp_alt point into other files
+            0x1d | 0x1f20 | 0x1f21 => skip(cursor, u64::from(unit.offset_size))?,
+            // DW_FORM_line_strp
+            0x1f => Value::LineStrp(read_offset(cursor, unit.offset_size)?),
+            // DW_FORM_implicit_const
+            0x21 => Value::Constant(implicit as u64),
+            // DW_FORM_loclistx, DW_FORM_rnglistx
+            0x22 | 0x23 => {
+                uleb(cursor)?;
+                Value::Other
+            }
+            // DW_FORM_strx1 to 4
+            0x25..=0x28 => Value::StringIndex(read_sized(cursor, (form - 0x24) as u8)?),
+            // DW_FORM_addrx1 to 4
+            0x29..=0x2c => Value::AddressIndex(read_sized(cursor, (form - 0x28) as u8)?),
+            _ => return None,
+        })
+    }
+
+    fn string(&self, unit: &Unit, value: Value<'a>) -> Option<&'a str> {
+        match value {
+            Value::String(string) => Some(string),
+            Value::Strp(offset) => c_string(self.str, offset),
+            Value::LineStrp(offset) => c_string(self.line_str, offset),
+            Value::StringIndex(index) => {
+                let size = u64::from(unit.offset_size);
+                let mut cursor = Cursor::new(self.str_offsets);
+                cursor.set_position(
+                    unit.str_offsets_base
+                        .checked_add(index.checked_mul(size)?)?,
+                );
+                c_string(self.str, read_offset(&mut cursor, unit.offset_size)?)
+            }
+            _ => None,
+        }
+    }
+
+    fn address(&self, unit: &Unit, value: Value) -> Option<u64> {
+        match value {
+            Value::Address(address) => Some(address),
+            Value::AddressIndex(index) => {
+                let size = u64::from(unit.address_size);
+                let mut cursor = Cursor::new(self.addr);
+                cursor.set_position(unit.addr_base.checked_add(index.checked_mul(size)?)?);
+                read_sized(&mut cursor, unit.address_size)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Reads a section offset of 32-bit or 64-bit DWARF
+fn read_offset(cursor: &mut Cursor<&[u8]>, offset_size: u8) -> Option<u64> {
+    read_sized(cursor, offset_size)
+}
+
+/// Reads a little endian value of 1 to 8 bytes
+fn read_sized(cursor: &mut Cursor<&[u8]>, size: u8) -> Option<u64> {
+    if !(1..=8).contains(&size) {
+        return None;
+    }
+    cursor.read_uint::<LE>(usize::from(size)).ok()
+}
+
+/// The NUL terminated string at `offset` of `data`
+fn c_string(data: &[u8], offset: u64) -> Option<&str> {
+    let data = data.get(usize::try_from(offset).ok()?..)?;
+    let length = data.iter().position(|&b| b == 0)?;
+    std::str::from_utf8(&data[..length]).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A DWARF 4 compile unit with 8 byte addresses around `entries`
+    fn unit4(entries: &[u8]) -> Vec<u8> {
+        let mut unit = (entries.len() as u32 + 7).to_le_bytes().to_vec();
+        unit.extend([4, 0, 0, 0, 0, 0, 8]);
+        unit.extend(entries);
+        unit
+    }
+
+    /// Size of the header of `unit4`, entry offsets within the unit start from here
+    const UNIT4_HEADER: usize = 11;
+
+    #[test]
+    fn reads_dwarf_4_subprograms() {
+        #[rustfmt::skip]
+        let abbrev = [
+            // compile unit with children: DW_AT_name as a string
+            1, 0x11, 1, 0x03, 0x08, 0, 0,
+            // definition: addr low_pc, data4 high_pc, strp linkage name, string name
+            2, 0x2e, 0, 0x11, 0x01, 0x12, 0x06, 0x6e, 0x0e, 0x03, 0x08, 0, 0,
+            // declaration: strp name
+            3, 0x2e, 0, 0x03, 0x0e, 0, 0,
+            // definition of a declaration: ref4 specification, addr low_pc, data4 high_pc
+            4, 0x2e, 0, 0x47, 0x13, 0x11, 0x01, 0x12, 0x06, 0, 0,
+            0,
+        ];
+        let str = b"_ZN4demo5first17h0123456789abcdefE\0new\0";
+
+        let mut entries = vec![1];
+        entries.extend(b"demo.rs\0");
+        entries.push(2);
+        entries.extend(0x1000u64.to_le_bytes());
+        entries.extend(0x20u32.to_le_bytes());
+        entries.extend(0u32.to_le_bytes());
+        entries.extend(b"first\0");
+        let declaration = (UNIT4_HEADER + entries.len()) as u32;
+        entries.push(3);
+        entries.extend(35u32.to_le_bytes());
+        entries.push(4);
+        entries.extend(declaration.to_le_bytes());
+        entries.extend(0x2000u64.to_le_bytes());
+        entries.extend(8u32.to_le_bytes());
+        // discarded by the linker
+        entries.push(2);
+        entries.extend(0u64.to_le_bytes());
+        entries.extend(8u32.to_le_bytes());
+        entries.extend(0u32.to_le_bytes());
+        entries.extend(b"gone\0");
+        entries.push(0);
+
+        let info = unit4(&entries);
+        let sections = Sections {
+            info: &info,
+            abbrev: &abbrev,
+            str,
+            ..Sections::default()
+        };
+        assert_eq!(
+            sections.subprograms(),
+            [
+                Subprogram {
+                    address: 0x1000,
+                    size: Some(0x20),
+                    name: "_ZN4demo5first17h0123456789abcdefE",
+                },
+                Subprogram {
+                    address: 0x2000,
+                    size: Some(8),
+                    name: "new",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_indexed_dwarf_5_forms() {
+        #[rustfmt::skip]
+        let abbrev = [
+            // compile unit: sec_offset str_offsets_base and addr_base
+            1, 0x11, 1, 0x72, 0x17, 0x73, 0x17, 0, 0,
+            // definition: addrx low_pc, data1 high_pc, strx1 name
+            2, 0x2e, 0, 0x11, 0x1b, 0x12, 0x0b, 0x03, 0x25, 0, 0,
+            0,
+        ];
+        let str = b"alpha\0beta\0";
+        let mut str_offsets = vec![0; 8];
+        str_offsets.extend(0u32.to_le_bytes());
+        str_offsets.extend(6u32.to_le_bytes());
+        let mut addr = vec![0; 8];
+        addr.extend(0x3000u64.to_le_bytes());
+        addr.extend(0x4000u64.to_le_bytes());
+
+        let mut entries = vec![1];
+        entries.extend(8u32.to_le_bytes());
+        entries.extend(8u32.to_le_bytes());
+        entries.extend([2, 1, 0x10, 1]);
+        entries.extend([2, 0, 0x20, 0]);
+        entries.push(0);
+        let mut info = (entries.len() as u32 + 8).to_le_bytes().to_vec();
+        info.extend([5, 0, 0x01, 8, 0, 0, 0, 0]);
+        info.extend(entries);
+
+        let sections = Sections {
+            info: &info,
+            abbrev: &abbrev,
+            str,
+            str_offsets: &str_offsets,
+            addr: &addr,
+            ..Sections::default()
+        };
+        assert_eq!(
+            sections.subprograms(),
+            [
+                Subprogram {
+                    address: 0x4000,
+                    size: Some(0x10),
+                    name: "beta",
+                },
+                Subprogram {
+                    address: 0x3000,
+                    size: Some(0x20),
+                    name: "alpha",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_malformed_units() {
+        #[rustfmt::skip]
+        let abbrev = [
+            // definition: addr low_pc, string name
+            1, 0x2e, 0, 0x11, 0x01, 0x03, 0x08, 0, 0,
+            0,
+        ];
+        let function = |address: u64, name: &str| {
+            let mut entry = vec![1];
+            entry.extend(address.to_le_bytes());
+            entry.extend(name.as_bytes());
+            entry.push(0);
+            entry
+        };
+
+        // an unknown abbreviation code loses the rest of its unit only
+        let mut first = function(0x10, "kept");
+        first.push(9);
+        first.extend(function(0x20, "lost"));
+        let mut info = unit4(&first);
+        info.extend(unit4(&function(0x30, "next")));
+        // a truncated unit ends the section
+        let mut truncated = unit4(&function(0x40, "truncated"));
+        truncated.truncate(truncated.len() - 4);
+        info.extend(truncated);
+
+        let sections = Sections {
+            info: &info,
+            abbrev: &abbrev,
+            ..Sections::default()
+        };
+        let names: Vec<_> = sections.subprograms().iter().map(|s| s.name).collect();
+        assert_eq!(names, ["kept", "next"]);
+        assert_eq!(sections.subprograms()[0].size, None);
+    }
+}
@@ -0,0 +1,101 @@
+//! Loading of the analyzed ELFs
+//!
+//! With the `mmap` feature the file is mapped on Unix instead of read, debug builds can be hundreds of MB large and only
+//! a fraction of that, the symbol table and `.stack_sizes`, is looked at.
+
+use std::{fs::File, io::Read, ops::Deref, path::Path};
+
+/// The contents of an ELF, mapped or read into memory
+pub enum ElfData {
+    Read(Vec<u8>),
+    #[cfg(all(feature = "mmap", unix))]
+    Mapped(Mapping),
+}
+
+impl ElfData {
+    /// Maps the file at `path`, falls back to reading it if it can't be mapped, e.g. because it's empty or a pipe
+    pub fn load(path: &Path) -> std::io::Result<ElfData> {
+        let mut file = File::open(path)?;
+        #[cfg(all(feature = "mmap", unix))]
+        match Mapping::of(&file) {
+            Ok(mapping) => return Ok(ElfData::Mapped(mapping)),
+            Err(err) => log::debug!("Reading {} instead of mapping it: {err}", path.display()),
+        }
+        let mut elf = Vec::new();
+        file.read_to_end(&mut elf)?;
+        Ok(ElfData::Read(elf))
+    }
+}
+
+impl From<Vec<u8>> for ElfData {
+    fn from(elf: Vec<u8>) -> ElfData {
+        ElfData::Read(elf)
+    }
+}
+
+impl Deref for ElfData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ElfData::Read(elf) => elf,
+            #[cfg(all(feature = "mmap", unix))]
+            ElfData::Mapped(mapping) => mapping,
+        }
+    }
+}
+
+/// A read-only private mapping of a whole file
+///
+/// The mapping is page aligned, as `xmas-elf` needs it. Truncating the file while it's mapped makes accessing the
+/// lost pages fault, like for every mapped file; cargo replaces its artifacts instead of truncating them.
+#[cfg(all(feature = "mmap", unix))]
+pub struct Mapping {
+    address: *mut std::ffi::c_void,
+    len: usize,
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Mapping {
+    fn of(file: &File) -> std::io::Result<Mapping> {
+        use rustix::mm::{mmap, MapFlags, ProtFlags};
+
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| std::io::Error::other("the file doesn't fit the address space"))?;
+        if len == 0 {
+            return Err(std::io::Error::other("empty files can't be mapped"));
+        }
+        // SAFETY: a new mapping of `len` bytes is created, it's only read and unmapped on drop
+        let address = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                ProtFlags::READ,
+                MapFlags::PRIVATE,
+                file,
+                0,
+            )
+        }?;
+        Ok(Mapping { address, len })
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Deref for Mapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the mapping is readable and `len` bytes long until it's dropped
+        unsafe { std::slice::from_raw_parts(self.address.cast(), self.len) }
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created by `mmap` with this address and length and isn't referenced anymore
+        if let Err(err) = unsafe { rustix::mm::munmap(self.address, self.len) } {
+            log::debug!("Failed to unmap an ELF: {err}");
+        }
+    }
+}
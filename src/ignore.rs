@@ -0,0 +1,68 @@
+use std::path::Path;
+
+use anyhow::Context;
+use regex::Regex;
+
+use crate::report::{self, Row};
+
+/// Function name patterns read from an `--ignore-file`
+///
+/// One pattern per line, `#` starts a comment. Patterns are globs unless prefixed with `re:`, which makes
+/// them a regular expression that has to match a part of the name. Crate disambiguators and legacy symbol
+/// hashes are removed from the names before matching.
+#[derive(Debug, Default)]
+pub struct IgnoreList {
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Debug)]
+enum Pattern {
+    Glob(glob::Pattern),
+    Regex(Regex),
+}
+
+impl IgnoreList {
+    pub fn load(path: &Path) -> anyhow::Result<IgnoreList> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        let mut patterns = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split_once('#').map(|(l, _)| l).unwrap_or(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let pattern = if let Some(re) = line.strip_prefix("re:") {
+                Regex::new(re.trim())
+                    .map(Pattern::Regex)
+                    .map_err(anyhow::Error::from)
+            } else {
+                glob::Pattern::new(line)
+                    .map(Pattern::Glob)
+                    .map_err(anyhow::Error::from)
+            };
+            patterns.push(pattern.with_context(|| format!("{}:{}", path.display(), i + 1))?);
+        }
+
+        Ok(IgnoreList { patterns })
+    }
+
+    /// Whether any name of the function matches one of the patterns
+    pub fn matches(&self, row: &Row) -> bool {
+        if self.patterns.is_empty() {
+            return false;
+        }
+
+        row.names.iter().any(|name| self.matches_name(name))
+    }
+
+    /// Whether the demangled or mangled symbol `name` matches one of the patterns
+    pub fn matches_name(&self, name: &str) -> bool {
+        let name = report::without_hashes(&rustc_demangle::demangle(name).to_string());
+        self.patterns.iter().any(|pattern| match pattern {
+            Pattern::Glob(glob) => glob.matches(&name),
+            Pattern::Regex(re) => re.is_match(&name),
+        })
+    }
+}
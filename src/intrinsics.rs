@@ -0,0 +1,200 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+
+use crate::report::Row;
+
+/// `--intrinsic-stacks` value selecting the built-in table instead of a file
+pub const ARM_EABI: &str = "arm-eabi";
+
+/// Assumed frames of the ARM EABI helpers of `compiler_builtins`, upper bounds for Thumb release builds
+const ARM_EABI_TABLE: &str = "\
+__aeabi_memcpy 16
+__aeabi_memcpy4 16
+__aeabi_memcpy8 16
+__aeabi_memmove 16
+__aeabi_memmove4 16
+__aeabi_memmove8 16
+__aeabi_memset 16
+__aeabi_memset4 16
+__aeabi_memset8 16
+__aeabi_memclr 16
+__aeabi_memclr4 16
+__aeabi_memclr8 16
+memcpy 16
+memmove 16
+memset 16
+memcmp 16
+__aeabi_uidiv 8
+__aeabi_idiv 8
+__aeabi_uidivmod 16
+__aeabi_idivmod 16
+__aeabi_uldivmod 32
+__aeabi_ldivmod 32
+__udivdi3 32
+__divdi3 32
+__umoddi3 32
+__moddi3 32
+__udivmoddi4 32
+__aeabi_fadd 16
+__aeabi_fsub 16
+__aeabi_fmul 16
+__aeabi_fdiv 16
+__aeabi_dadd 32
+__aeabi_dsub 32
+__aeabi_dmul 32
+__aeabi_ddiv 48
+";
+
+/// Assumed stack sizes of compiler intrinsics, read from an `--intrinsic-stacks` file
+///
+/// Each line holds a symbol and its assumed number of bytes, `#` starts a comment, like an `--observed` file.
+/// Intrinsics are often undefined in the analyzed ELF or come without a `.stack_sizes` entry, their frames
+/// would be missing from the report otherwise.
+#[derive(Debug, Default)]
+pub struct IntrinsicStacks {
+    sizes: HashMap<String, u64>,
+}
+
+impl IntrinsicStacks {
+    /// Reads the `--intrinsic-stacks` table if one is given
+    pub fn of(path: Option<&Path>) -> anyhow::Result<Option<IntrinsicStacks>> {
+        match path {
+            None => Ok(None),
+            Some(path) if path == Path::new(ARM_EABI) && !path.exists() => {
+                Ok(Some(IntrinsicStacks::parse(ARM_EABI_TABLE, ARM_EABI)?))
+            }
+            Some(path) => {
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                Ok(Some(IntrinsicStacks::parse(
+                    &content,
+                    &path.display().to_string(),
+                )?))
+            }
+        }
+    }
+
+    fn parse(content: &str, source: &str) -> anyhow::Result<IntrinsicStacks> {
+        let mut sizes = HashMap::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split_once('#').map(|(l, _)| l).unwrap_or(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let Some((name, bytes)) = line.rsplit_once(char::is_whitespace) else {
+                bail!(
+                    "{source}:{}: expected a symbol and a number of bytes",
+                    i + 1
+                );
+            };
+            let bytes = bytes
+                .parse()
+                .with_context(|| format!("{source}:{}: invalid number of bytes", i + 1))?;
+            sizes.insert(name.trim().to_string(), bytes);
+        }
+
+        Ok(IntrinsicStacks { sizes })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.sizes.contains_key(name)
+    }
+
+    /// Gives the intrinsics in `rows` without a stack size their assumed one, and adds a row without code for
+    /// each of the `undefined` ones, returns the number of intrinsics accounted for
+    ///
+    /// Stack sizes read from `.stack_sizes` are kept, the assumed ones are marked as estimates.
+    pub fn apply(&self, rows: &mut Vec<Row>, undefined: &HashSet<&str>) -> usize {
+        let mut applied = 0;
+        for row in rows.iter_mut().filter(|row| row.stack.is_none()) {
+            if let Some(&size) = row.names.iter().find_map(|name| self.sizes.get(name)) {
+                row.stack = Some(size);
+                row.estimated = true;
+                applied += 1;
+            }
+        }
+
+        let mut undefined: Vec<_> = undefined
+            .iter()
+            .filter_map(|&name| Some((name, *self.sizes.get(name)?)))
+            .collect();
+        undefined.sort();
+        for (name, size) in undefined {
+            rows.push(Row {
+                address: 0,
+                names: vec![name.to_string()],
+                code: 0,
+                extent: None,
+                stack: Some(size),
+                estimated: true,
+                alias: false,
+                closures: 0,
+            });
+            applied += 1;
+        }
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(name: &str, stack: Option<u64>) -> Row {
+        Row {
+            address: 0x100,
+            names: vec![name.to_string()],
+            code: 64,
+            extent: None,
+            stack,
+            estimated: false,
+            alias: false,
+            closures: 0,
+        }
+    }
+
+    #[test]
+    fn assumes_the_frames_of_intrinsics() {
+        let table = IntrinsicStacks::parse(
+            "# a comment\n__aeabi_memcpy 16\n__udivdi3 32 # trailing\nmemset 8\n",
+            "test",
+        )
+        .unwrap();
+        let mut rows = vec![
+            row("app::main", None),
+            row("__udivdi3", None),
+            row("memset", Some(4)),
+        ];
+        let undefined = HashSet::from(["__aeabi_memcpy", "abort"]);
+        assert_eq!(table.apply(&mut rows, &undefined), 2);
+
+        let stacks: Vec<_> = rows
+            .iter()
+            .map(|row| (row.names[0].as_str(), row.code, row.stack, row.estimated))
+            .collect();
+        assert_eq!(
+            stacks,
+            [
+                ("app::main", 64, None, false),
+                ("__udivdi3", 64, Some(32), true),
+                ("memset", 64, Some(4), false),
+                ("__aeabi_memcpy", 0, Some(16), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_bad_entries() {
+        let err = IntrinsicStacks::parse("memcpy 16\nmemset lots\n", "table").unwrap_err();
+        assert_eq!(err.to_string(), "table:2: invalid number of bytes");
+        assert!(IntrinsicStacks::parse("memcpy\n", "table").is_err());
+        assert!(IntrinsicStacks::of(Some(Path::new(ARM_EABI)))
+            .unwrap()
+            .is_some_and(|table| table.contains("__aeabi_uldivmod")));
+    }
+}
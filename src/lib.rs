@@ -0,0 +1,12 @@
+//! Stack usage of the functions of an ELF file, as recorded in the `.stack_sizes` section LLVM emits with
+//! `-Z emit-stack-sizes`
+//!
+//! The analysis behind the `ssize` command line tool: [`analysis`] reads the functions and their stack sizes,
+//! [`budget`] checks them against stack limits and [`map`] reads the symbols of a linker map file to analyze
+//! stripped executables.
+
+pub mod analysis;
+pub mod budget;
+mod cfi;
+mod dwarf;
+pub mod map;
@@ -0,0 +1,292 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt,
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+use toml::Value;
+
+/// Flavor of the linker script we inject to retain the `.stack_sizes` section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// GNU ld - understands the `(INFO)` output section type
+    Gnu,
+    /// LLVM lld - older versions reject `(INFO)`, the section is non-allocatable anyway
+    Lld,
+}
+
+impl Dialect {
+    /// File name of the generated linker script
+    ///
+    /// Each dialect and each set of custom `attrs` gets its own name so switching changes the rustflags and
+    /// forces a relink
+    pub fn file_name(self, attrs: Option<&str>) -> String {
+        match (self, attrs) {
+            (_, Some(attrs)) => {
+                let mut hasher = DefaultHasher::new();
+                attrs.hash(&mut hasher);
+                format!("2374972342390lnk-{:08x}.x", hasher.finish() as u32)
+            }
+            (Dialect::Gnu, None) => "2374972342390lnk.x".to_string(),
+            (Dialect::Lld, None) => "2374972342390lnk-lld.x".to_string(),
+        }
+    }
+
+    /// Contents of the generated linker script
+    ///
+    /// `attrs` replace the attributes of the `.stack_sizes` output section, the same for both dialects.
+    pub fn script(self, attrs: Option<&str>) -> String {
+        if let Some(attrs) = attrs {
+            return format!(
+                "
+    SECTIONS
+    {{
+      /* attributes given with `--section-attrs` */
+      .stack_sizes {attrs} :
+      {{
+        KEEP(*(.stack_sizes));
+      }}
+    }}
+    "
+            );
+        }
+
+        match self {
+            Dialect::Gnu => {
+                "
+    SECTIONS
+    {
+      /* `INFO` makes the section not allocatable so it won't be loaded into memory */
+      .stack_sizes (INFO) :
+      {
+        KEEP(*(.stack_sizes));
+      }
+    }
+    "
+            }
+            Dialect::Lld => {
+                "
+    SECTIONS
+    {
+      /* the input section isn't allocatable so neither is the output section */
+      .stack_sizes :
+      {
+        KEEP(*(.stack_sizes));
+      }
+    }
+    "
+            }
+        }
+        .to_string()
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dialect::Gnu => write!(f, "GNU ld"),
+            Dialect::Lld => write!(f, "lld"),
+        }
+    }
+}
+
+/// Tries to figure out the linker used for `target` from `.cargo/config.toml`, the `rustflags` cargo passes and
+/// the target triple
+///
+/// Returns `None` if we can't tell - the caller should try both dialects then
+pub fn detect(config: Option<&Value>, target: &str, rustflags: &[String]) -> Option<Dialect> {
+    if let Some(dialect) = linker(config, target, rustflags).and_then(classify) {
+        return Some(dialect);
+    }
+
+    if let Some(dialect) = from_rustflags(rustflags) {
+        return Some(dialect);
+    }
+
+    if target.starts_with("xtensa") {
+        // Xtensa targets link through the GCC toolchain
+        Some(Dialect::Gnu)
+    } else if target.contains("-none") {
+        // bare-metal targets default to `rust-lld`
+        Some(Dialect::Lld)
+    } else {
+        None
+    }
+}
+
+/// Whether the linker used for `target` is a compiler driver like `arm-none-eabi-gcc`, those need the options
+/// of the actual linker wrapped in `-Wl,`
+pub fn is_driver(config: Option<&Value>, target: &str, rustflags: &[String]) -> bool {
+    let Some(linker) = linker(config, target, rustflags) else {
+        return false;
+    };
+
+    let name = Path::new(linker)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(linker);
+    name.ends_with("gcc") || name.ends_with("clang") || name == "cc" || name.ends_with("-cc")
+}
+
+/// The linker given by `-C linker=` in `rustflags` or as the `linker` of `target` in `.cargo/config.toml`
+///
+/// The rustflags come after the configured linker on the command line of rustc, so they win.
+fn linker<'a>(config: Option<&'a Value>, target: &str, rustflags: &'a [String]) -> Option<&'a str> {
+    codegen_options(rustflags)
+        .into_iter()
+        .rev()
+        .find_map(|option| option.strip_prefix("linker="))
+        .or_else(|| {
+            config
+                .and_then(|c| c.get("target"))
+                .and_then(|t| t.get(target))
+                .and_then(|t| t.get("linker"))
+                .and_then(|l| l.as_str())
+        })
+}
+
+/// The codegen options (`-C <OPTION>` and `-C<OPTION>`) among `rustflags`
+fn codegen_options(rustflags: &[String]) -> Vec<&str> {
+    let mut codegen = Vec::new();
+    let mut flags = rustflags.iter();
+    while let Some(flag) = flags.next() {
+        if flag == "-C" {
+            if let Some(next) = flags.next() {
+                codegen.push(next.as_str());
+            }
+        } else if let Some(rest) = flag.strip_prefix("-C") {
+            codegen.push(rest);
+        }
+    }
+    codegen
+}
+
+/// The dialect given by `-C linker-flavor=` or `-C link-arg=-fuse-ld=` in `rustflags`
+fn from_rustflags(rustflags: &[String]) -> Option<Dialect> {
+    for option in codegen_options(rustflags) {
+        if let Some(linker) = option.strip_prefix("linker-flavor=") {
+            if let Some(dialect) = classify(linker) {
+                return Some(dialect);
+            }
+        } else if let Some(linker) = option.strip_prefix("link-arg=-fuse-ld=") {
+            return classify(linker);
+        }
+    }
+
+    None
+}
+
+fn classify(linker: &str) -> Option<Dialect> {
+    // not the file stem, that turns `ld.lld` into `ld`
+    let name = Path::new(linker)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(linker);
+    let name = name.strip_suffix(".exe").unwrap_or(name);
+
+    if name.contains("lld") {
+        Some(Dialect::Lld)
+    } else if name.contains("gcc") || name == "ld" || name.ends_with("-ld") || name == "bfd" {
+        Some(Dialect::Gnu)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Value {
+        toml.parse().unwrap()
+    }
+
+    #[test]
+    fn classifies_linkers() {
+        assert_eq!(classify("rust-lld"), Some(Dialect::Lld));
+        assert_eq!(classify("ld.lld"), Some(Dialect::Lld));
+        assert_eq!(classify("/usr/bin/ld.lld"), Some(Dialect::Lld));
+        assert_eq!(classify("arm-none-eabi-gcc"), Some(Dialect::Gnu));
+        assert_eq!(classify("riscv32-esp-elf-ld"), Some(Dialect::Gnu));
+        assert_eq!(classify("ld"), Some(Dialect::Gnu));
+        assert_eq!(classify("link.exe"), None);
+    }
+
+    fn flags(flags: &[&str]) -> Vec<String> {
+        flags.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn detects_the_linker_of_the_target() {
+        let lld = config("[target.thumbv7em-none-eabihf]\nlinker = \"rust-lld\"\n");
+        assert_eq!(
+            detect(Some(&lld), "thumbv7em-none-eabihf", &[]),
+            Some(Dialect::Lld)
+        );
+
+        let gcc = config("[target.thumbv7em-none-eabihf]\nlinker = \"arm-none-eabi-gcc\"\n");
+        assert_eq!(
+            detect(Some(&gcc), "thumbv7em-none-eabihf", &[]),
+            Some(Dialect::Gnu)
+        );
+        assert!(is_driver(Some(&gcc), "thumbv7em-none-eabihf", &[]));
+        assert!(!is_driver(Some(&lld), "thumbv7em-none-eabihf", &[]));
+    }
+
+    #[test]
+    fn detects_the_linker_from_rustflags() {
+        let detect =
+            |rustflags: &[&str]| detect(None, "x86_64-unknown-linux-gnu", &flags(rustflags));
+        assert_eq!(detect(&["-C", "link-arg=-fuse-ld=lld"]), Some(Dialect::Lld));
+        assert_eq!(detect(&["-Clink-arg=-fuse-ld=bfd"]), Some(Dialect::Gnu));
+        assert_eq!(detect(&["-C", "linker=ld.lld"]), Some(Dialect::Lld));
+        assert_eq!(detect(&["-C", "opt-level=3"]), None);
+    }
+
+    #[test]
+    fn rustflags_override_the_configured_linker() {
+        let lld = config("[target.x86_64-unknown-linux-gnu]\nlinker = \"rust-lld\"\n");
+        let gcc = flags(&["-C", "linker=gcc"]);
+        assert_eq!(
+            detect(Some(&lld), "x86_64-unknown-linux-gnu", &gcc),
+            Some(Dialect::Gnu)
+        );
+        assert!(is_driver(Some(&lld), "x86_64-unknown-linux-gnu", &gcc));
+        assert!(is_driver(
+            None,
+            "x86_64-unknown-linux-gnu",
+            &flags(&["-Clinker=/usr/bin/cc"])
+        ));
+        assert!(!is_driver(None, "x86_64-unknown-linux-gnu", &[]));
+    }
+
+    #[test]
+    fn falls_back_to_the_target_triple() {
+        assert_eq!(detect(None, "thumbv6m-none-eabi", &[]), Some(Dialect::Lld));
+        assert_eq!(
+            detect(None, "xtensa-esp32-none-elf", &[]),
+            Some(Dialect::Gnu)
+        );
+        assert_eq!(detect(None, "x86_64-unknown-linux-gnu", &[]), None);
+    }
+
+    #[test]
+    fn scripts_keep_the_section() {
+        let gnu = Dialect::Gnu.script(None);
+        assert!(gnu.contains("KEEP(*(.stack_sizes));"));
+        assert!(gnu.contains(".stack_sizes (INFO) :"));
+
+        let lld = Dialect::Lld.script(None);
+        assert!(lld.contains("KEEP(*(.stack_sizes));"));
+        assert!(lld.contains(".stack_sizes :"));
+        assert!(!lld.contains("(INFO)"));
+
+        let custom = Dialect::Lld.script(Some("(NOLOAD)"));
+        assert!(custom.contains(".stack_sizes (NOLOAD) :"));
+        assert_ne!(
+            Dialect::Gnu.file_name(Some("(NOLOAD)")),
+            Dialect::Gnu.file_name(None)
+        );
+    }
+}
@@ -1,15 +1,44 @@
+mod abi;
+mod cargo;
+mod compare;
+mod crates;
+mod doctor;
+mod elf_data;
+mod ignore;
+mod intrinsics;
+mod json;
+mod linker;
+mod observed;
+mod preset;
+mod report;
+mod snapshot;
+mod watch;
+
 use std::{
-    env,
-    path::{Component, PathBuf},
-    process::Command,
+    collections::{BTreeMap, BTreeSet},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
 };
 
-use anyhow::bail;
-use cargo_project::{Artifact, Profile, Project};
-use clap::Parser;
-use toml::Value;
+use abi::Abi;
+use anyhow::{bail, Context};
+use cargo::Build;
+use clap::{ArgAction, Parser, ValueEnum};
+use crates::Crates;
+use elf_data::ElfData;
+use ignore::IgnoreList;
+use intrinsics::IntrinsicStacks;
+use log::{info, trace, LevelFilter};
+use map::MapFile;
+use observed::Observed;
+use regex::Regex;
+use report::{BudgetCheck, BySection, Counts, Row, Summary};
+use snapshot::{FunctionSize, Sections, Snapshot};
+use ssize::{analysis, budget::BudgetReport, map};
+use xmas_elf::ElfFile;
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Clone, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Build only the specified binary
@@ -20,176 +49,1403 @@ struct Args {
     #[arg(long, value_name = "NAME")]
     example: Option<String>,
 
+    /// Build only the specified bench target
+    #[arg(long, value_name = "NAME")]
+    bench: Option<String>,
+
+    /// Build every binary, example, test and bench target and show the worst case of each function
+    #[arg(long, conflicts_with_all = ["bin", "example", "bench", "elf", "watch", "out_override"])]
+    all_targets: bool,
+
+    /// Build the binaries of every workspace member and show the worst case of each function
+    #[arg(long, conflicts_with_all = ["example", "bench", "all_targets", "elf", "watch", "out_override"])]
+    workspace: bool,
+
+    /// Skip this workspace member, can be given multiple times
+    #[arg(long, value_name = "PKG", requires = "workspace")]
+    exclude: Vec<String>,
+
+    /// Build for this target triple instead of the configured one
+    #[arg(long, value_name = "TRIPLE")]
+    target: Option<String>,
+
+    /// Target triple to fall back to when rustc can't tell the host, with `--elf` it also limits the dependencies
+    /// read from `cargo metadata` to the ones of this target
+    #[arg(long, value_name = "TRIPLE")]
+    assume_target: Option<String>,
+
+    /// Build for the custom target described by this JSON target specification
+    #[arg(long, value_name = "PATH", conflicts_with = "target")]
+    target_json: Option<PathBuf>,
+
+    /// Use the defaults of this preset defined in `.ssize.toml`, options given on the command line take precedence
+    #[arg(long, value_name = "NAME")]
+    preset: Option<String>,
+
     /// Space-separated list of features to activate
     #[arg(long, value_name = "FEATURES")]
     features: Option<String>,
 
+    /// Build with and without this feature and show how the stack usage of the functions changes
+    #[arg(
+        long,
+        value_name = "FEATURE",
+        conflicts_with_all = ["all_features", "all_targets", "workspace", "elf", "watch", "no_build", "build_command"]
+    )]
+    compare_features: Option<String>,
+
+    /// Build with the rustup toolchains A and B and show how the stack usage of the functions changes
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["A", "B"],
+        conflicts_with_all = ["compare_features", "all_targets", "workspace", "elf", "watch", "no_build", "build_command", "rustc"]
+    )]
+    compare_toolchains: Vec<String>,
+
     /// Activate all available features
     #[arg(long)]
     all_features: bool,
 
+    /// Do not activate the `default` feature
+    #[arg(long)]
+    no_default_features: bool,
+
     /// Only show functions whose stack size is greater or equals to this
     #[arg(long)]
     min_stack: Option<u64>,
 
+    /// Only print aggregates of the functions (count, totals, maximum and mean) and the budget result, as a table,
+    /// JSON, TOML or a property list
+    #[arg(long)]
+    summary_only: bool,
+
+    /// Estimate the stack size of the functions without a `.stack_sizes` entry from the call frame information
+    /// in `.eh_frame` or `.debug_frame`, for builds that can't emit stack sizes, e.g. on stable
+    #[arg(long)]
+    from_cfi: bool,
+
+    /// Name the functions whose symbols are only local labels, and the `.stack_sizes` entries without a symbol,
+    /// after the functions of the debug information (`DW_AT_linkage_name` or `DW_AT_name`)
+    #[arg(long)]
+    names_from_debug: bool,
+
+    /// Only print the numbers of defined functions, undefined symbols, functions with a stack size and
+    /// allocatable sections, a quick check that the build emitted symbols and stack sizes at all
+    #[arg(long, conflicts_with = "summary_only")]
+    count_only: bool,
+
+    /// Hide functions without code and stack usage, e.g. the symbols left behind by functions that were inlined
+    /// everywhere
+    #[arg(long)]
+    quiet_zero: bool,
+
     /// Override the path of the resulting ELF - use if for some reason it's not found
     #[arg(long)]
     out_override: Option<PathBuf>,
+
+    /// Build for this CPU (`-C target-cpu`)
+    #[arg(long, value_name = "CPU")]
+    target_cpu: Option<String>,
+
+    /// Comma-separated list of target features to enable or disable (`-C target-feature`), e.g. `+fp,-neon`
+    #[arg(long, value_name = "LIST")]
+    target_feature: Option<String>,
+
+    /// Build with this panic strategy (`-C panic`)
+    #[arg(long, value_enum)]
+    panic: Option<PanicStrategy>,
+
+    /// Build with this link-time optimization setting (`profile.release.lto`)
+    #[arg(long, value_enum, conflicts_with = "build_command")]
+    lto: Option<Lto>,
+
+    /// Analyze this ELF instead of building, can be given multiple times, `-` reads it from stdin
+    #[arg(long, value_name = "PATH")]
+    elf: Vec<PathBuf>,
+
+    /// Show the functions of all ELFs passed via `--elf` in one table
+    #[arg(long)]
+    combined: bool,
+
+    /// List the binary, example, test and bench targets of the package and their required features, of every
+    /// workspace member with `--workspace`
+    #[arg(long, conflicts_with_all = ["elf", "watch"])]
+    list_targets: bool,
+
+    /// Rebuild and show the report again whenever a file of the package changes
+    #[arg(long, conflicts_with = "elf")]
+    watch: bool,
+
+    /// Append the total and maximum stack and the largest frame of every `--watch` build to this file, as CSV
+    /// if it ends in `.csv` and as NDJSON otherwise
+    #[arg(long, value_name = "FILE", requires = "watch")]
+    watch_history: Option<PathBuf>,
+
+    /// Analyze the artifact of the last build instead of building again
+    #[arg(long, visible_alias = "use-existing", conflicts_with_all = ["elf", "watch"])]
+    no_build: bool,
+
+    /// Build using this command instead of `cargo build`, see the README for the placeholders
+    #[arg(long, value_name = "TEMPLATE", conflicts_with = "elf")]
+    build_command: Option<String>,
+
+    /// Build these standard library crates from source with `-Z build-std`, e.g. `core,alloc`
+    #[arg(long, value_name = "CRATES", conflicts_with_all = ["elf", "build_command"])]
+    build_std: Option<String>,
+
+    /// Attributes of the `.stack_sizes` output section in the injected linker script instead of `(INFO)`,
+    /// e.g. `(NOLOAD)` or an empty string, for linkers dropping the section with the default script
+    #[arg(long, value_name = "STR", conflicts_with_all = ["elf", "build_command"])]
+    section_attrs: Option<String>,
+
+    /// Don't take the rustflags, linker and profile settings of `.cargo/config.toml` into account
+    #[arg(long, conflicts_with = "elf")]
+    no_cargo_config: bool,
+
+    /// Build with this compiler, passed on to cargo as `RUSTC`
+    #[arg(long, value_name = "PATH")]
+    rustc: Option<PathBuf>,
+
+    /// Check that the compiler in use, behind a `RUSTC_WRAPPER` if set, emits stack sizes before building
+    #[arg(long, conflicts_with_all = ["elf", "doctor"])]
+    verify_rustc: bool,
+
+    /// Run cargo without accessing the network
+    #[arg(long)]
+    offline: bool,
+
+    /// Require `Cargo.lock` to be up to date, like `cargo --locked`
+    #[arg(long)]
+    locked: bool,
+
+    /// Require `Cargo.lock` to be up to date and don't access the network, like `cargo --frozen`
+    #[arg(long)]
+    frozen: bool,
+
+    /// Fail if a function needs more stack than this many bytes
+    #[arg(long, value_name = "BYTES", conflicts_with = "watch")]
+    fail_over: Option<u64>,
+
+    /// Fail if the stack frames of all functions add up to more than this many bytes
+    #[arg(long, value_name = "BYTES", conflicts_with = "watch")]
+    fail_over_total: Option<u64>,
+
+    /// Fail if a function not contained in the `--baseline` needs more stack than this many bytes
+    #[arg(
+        long,
+        value_name = "BYTES",
+        requires = "baseline",
+        conflicts_with = "watch"
+    )]
+    fail_on_new: Option<u64>,
+
+    /// Fail if a function of the `--baseline` needs more stack than there, by at least `--delta-threshold` bytes
+    #[arg(long, requires = "baseline", conflicts_with = "watch")]
+    fail_on_regression: bool,
+
+    /// Fail if the ELF references undefined symbols, e.g. an accidental `std` dependency of a `no_std` program
+    #[arg(long, conflicts_with = "watch")]
+    fail_on_undefined: bool,
+
+    /// Don't fail on the undefined symbols matching the patterns in this file, in the format of `--ignore-file`
+    #[arg(long, value_name = "PATH", requires = "fail_on_undefined")]
+    allow_undefined: Option<PathBuf>,
+
+    /// Assume the stack sizes in this file, one `symbol bytes` pair per line, for the compiler intrinsics that are
+    /// undefined or lack a stack size, `arm-eabi` selects a built-in table of the ARM EABI helpers. Undefined
+    /// intrinsics in it don't fail `--fail-on-undefined`
+    #[arg(long, value_name = "FILE")]
+    intrinsic_stacks: Option<PathBuf>,
+
+    /// Exclude the functions matching the patterns in this file from `--fail-over`, `--fail-on-new` and
+    /// `--fail-on-regression`
+    #[arg(long, value_name = "PATH")]
+    ignore_file: Option<PathBuf>,
+
+    /// Leave out the functions placed in this section or its subsections, e.g. `.boot`, can be given multiple times
+    #[arg(long, value_name = "NAME")]
+    exclude_section: Vec<String>,
+
+    /// Also hide the functions matched by `--ignore-file` from the report
+    #[arg(long, requires = "ignore_file")]
+    hide_ignored: bool,
+
+    /// Only show functions of dependencies, leaving out the crates of the workspace and the standard library
+    #[arg(long)]
+    deps_only: bool,
+
+    /// Make stack sizes comparable across targets by including the return address pushed by call instructions
+    #[arg(long)]
+    abi_normalize: bool,
+
+    /// Round the stack sizes up to the stack alignment of the architecture, frames of leaf functions don't need
+    /// it and are overestimated then
+    #[arg(long)]
+    align: bool,
+
+    /// Metric to sort by
+    #[arg(long, value_enum, default_value_t = SortBy::Stack)]
+    sort: SortBy,
+
+    /// Stack available to the program, adds a column with the share of it each function needs
+    #[arg(long, value_name = "N")]
+    stack_size: Option<u64>,
+
+    /// Unit of `--stack-size`, a word is as wide as a pointer of the target
+    #[arg(long, value_enum, default_value_t = StackUnit::Bytes, requires = "stack_size")]
+    stack_size_unit: StackUnit,
+
+    /// Decimal places of the stack share
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "stack_size")]
+    percent_precision: usize,
+
+    /// Only show the functions accounting for this percentage of the total of the `--sort` metric
+    #[arg(long, value_name = "P", value_parser = parse_percentile)]
+    percentile: Option<f64>,
+
+    /// Match stack sizes and aliases to functions only by their exact address, ignoring the thumb bit
+    #[arg(long)]
+    no_thumb_fixup: bool,
+
+    /// Sum up the code and stack of closures into the function defining them, in the table only
+    #[arg(long)]
+    collapse_closures: bool,
+
+    /// Show the address of each function
+    #[arg(long)]
+    addresses: bool,
+
+    /// Show the extent of each function computed from the address of the next one
+    #[arg(long)]
+    computed_size: bool,
+
+    /// Show every alias of a function as its own row instead of merging them
+    #[arg(long)]
+    split_aliases: bool,
+
+    /// Render names in the syntax of this symbol mangling scheme, names are always compared independent of it
+    #[arg(long, value_enum, default_value_t = Mangling::Auto)]
+    mangling: Mangling,
+
+    /// Which of a function's aliases to show first
+    #[arg(long, value_enum, default_value_t = PreferName::First)]
+    prefer_name: PreferName,
+
+    /// Remove this leading module path from the shown names, can be given multiple times
+    #[arg(long, value_name = "PREFIX")]
+    strip_prefix: Vec<String>,
+
+    /// Show paths relative to the current directory
+    #[arg(long)]
+    relative_paths: bool,
+
+    /// Remove this leading directory from the shown paths, can be given multiple times
+    #[arg(long, value_name = "PREFIX")]
+    strip_path_prefix: Vec<PathBuf>,
+
+    /// Leave the functions whose stack size changed by less than this many bytes out of the diffs of `--baseline`,
+    /// `--compare-features` and `--compare-toolchains` and out of `--fail-on-regression`, new and removed
+    /// functions are always shown
+    #[arg(long, value_name = "BYTES", default_value_t = 0)]
+    delta_threshold: u64,
+
+    /// Compare against a snapshot previously written by `--output`
+    #[arg(long, value_name = "FILE")]
+    baseline: Option<PathBuf>,
+
+    /// List functions whose stack size exceeds their code size by more than this factor
+    #[arg(long, value_name = "N")]
+    stack_code_ratio: Option<f64>,
+
+    /// List functions of the workspace without a stack size, they likely allocate stack dynamically
+    #[arg(long)]
+    flag_dynamic: bool,
+
+    /// Print a histogram of the stack sizes, bucketed by powers of two unless `--buckets` is given
+    #[arg(long)]
+    histogram: bool,
+
+    /// Comma-separated lower bounds in bytes of the histogram buckets, e.g. `0,128,1024`
+    #[arg(
+        long,
+        value_name = "BYTES",
+        value_delimiter = ',',
+        requires = "histogram"
+    )]
+    buckets: Vec<u64>,
+
+    /// Sum up the code and stack of the functions in each code section
+    #[arg(long)]
+    by_section: bool,
+
+    /// Sum up the code and stack of each workspace crate, e.g. to tell a binary from its library
+    #[arg(long)]
+    by_local_crate: bool,
+
+    /// Sum up the code and stack of the functions grouped by the first capture group of this regular expression
+    /// in their demangled names, e.g. `^([a-z_]+)::` groups by crate
+    #[arg(long, value_name = "PATTERN")]
+    group_by_regex: Option<Regex>,
+
+    /// Sum up the code and stack of the panic and formatting machinery
+    #[arg(long)]
+    highlight_fmt: bool,
+
+    /// Compare against the runtime stack usage listed in this file, one `<symbol> <bytes>` per line
+    #[arg(long, value_name = "FILE")]
+    observed: Option<PathBuf>,
+
+    /// Write a snapshot of the section and function sizes to this file
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Write a snapshot for each analyzed ELF into this directory, named after the executable
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+
+    /// Print every decoded `.stack_sizes` entry with its offset and the function it was attributed to
+    #[arg(long)]
+    dump_stack_sizes: bool,
+
+    /// Subtract this base from the `.stack_sizes` addresses before matching them, for images where those are
+    /// absolute but the symbol values are offsets
+    #[arg(long, value_name = "ADDR", value_parser = parse_address)]
+    relocate_base: Option<u64>,
+
+    /// Skip `.stack_sizes` records claiming more than this many bytes of stack as corrupt metadata
+    #[arg(long, value_name = "BYTES", default_value_t = analysis::MAX_FRAME)]
+    max_frame: u64,
+
+    /// Recover the functions of a stripped ELF from this GNU ld or LLD map file
+    #[arg(long, value_name = "PATH")]
+    map: Option<PathBuf>,
+
+    /// Output format of the report
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+
+    /// Check that the JSON report parses back to the same functions, for testing the serialization
+    #[arg(long, hide = true)]
+    round_trip: bool,
+
+    /// Check that the toolchain and target support stack size information instead of building
+    #[arg(long)]
+    doctor: bool,
+
+    /// Print diagnostics, repeat for more detail (-v info, -vv debug, -vvv trace)
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
 }
 
-fn main() -> anyhow::Result<()> {
-    env_logger::init();
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum SortBy {
+    /// Stack size
+    Stack,
+    /// Code size
+    Code,
+    /// Stack size per byte of code, functions without code count as one byte
+    Ratio,
+}
 
-    let args = Args::parse();
+impl SortBy {
+    /// Size the rows are sorted by and `--percentile` sums up, `ratio` falls back to the stack size
+    fn key(self, row: &Row) -> u64 {
+        match self {
+            SortBy::Stack | SortBy::Ratio => row.stack.unwrap_or(0),
+            SortBy::Code => row.code,
+        }
+    }
 
-    let meta = rustc_version::version_meta()?;
-    let host = meta.host;
-    let cwd = env::current_dir()?;
-    let project = Project::query(cwd)?;
-    let target = project.target().unwrap_or(&host);
-
-    let config = std::fs::read_to_string(".cargo/config.toml");
-    let rustflags = if let Ok(content) = config {
-        let value = content.parse::<Value>()?;
-        if let Some(build) = value["build"].as_table() {
-            if build.contains_key("rustflags") {
-                let rf = build["rustflags"].as_array();
-                if let Some(rf) = rf {
-                    let mut rf_str = String::new();
-                    for v in rf {
-                        rf_str.push_str("\"");
-                        rf_str.push_str(&v.as_str().unwrap().replace("\"", "\\\""));
-                        rf_str.push_str("\",");
-                    }
-                    rf_str
-                } else {
-                    "".to_string()
-                }
-            } else {
-                "".to_string()
+    /// Orders the rows, largest first
+    ///
+    /// Ties are ordered like `Functions::by_stack_desc`, aliases stay behind the row of their function.
+    fn compare(self, a: &Row, b: &Row) -> std::cmp::Ordering {
+        let by_key = || {
+            analysis::largest_first(
+                (self.key(a), a.address, (a.alias, &a.names)),
+                (self.key(b), b.address, (b.alias, &b.names)),
+            )
+        };
+        match self {
+            SortBy::Ratio => {
+                let ratio = |row: &Row| row.stack.unwrap_or(0) as f64 / row.code.max(1) as f64;
+                ratio(b).total_cmp(&ratio(a)).then_with(by_key)
             }
-        } else {
-            "".to_string()
+            _ => by_key(),
+        }
+    }
+}
+
+impl std::fmt::Display for SortBy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SortBy::Stack => write!(f, "stack"),
+            SortBy::Code => write!(f, "code"),
+            SortBy::Ratio => write!(f, "ratio"),
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum StackUnit {
+    Bytes,
+    /// Pointer sized words
+    Words,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Mangling {
+    /// Show every name the way it was mangled
+    Auto,
+    /// Show v0 mangled names in the legacy syntax
+    Legacy,
+    /// Show legacy mangled names without their hash
+    V0,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum PreferName {
+    /// The alphabetically first demangled name, the names are sorted to be the same on every build
+    First,
+    /// A name demangling as a Rust symbol
+    Rust,
+    /// The shortest name
+    Short,
+    /// The longest name
+    Long,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// Human readable table
+    Table,
+    Json,
+    Toml,
+    /// One line of comma-separated values per function
+    Csv,
+    /// XML property list with the structure of the JSON document
+    Plist,
+    /// Compact binary snapshot of all functions, readable by `--baseline`
+    Binary,
+    /// Rust source defining `STACK_BUDGET: &[(&str, u32)]`
+    RustConst,
+    /// Prometheus text exposition format
+    Prometheus,
+    /// JUnit XML with a failing test case for each function over `--fail-over`/`--fail-on-new`
+    Junit,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum PanicStrategy {
+    Abort,
+    Unwind,
+}
+
+impl std::fmt::Display for PanicStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PanicStrategy::Abort => write!(f, "abort"),
+            PanicStrategy::Unwind => write!(f, "unwind"),
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Lto {
+    Off,
+    Thin,
+    Fat,
+}
+
+impl std::fmt::Display for Lto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lto::Off => write!(f, "off"),
+            Lto::Thin => write!(f, "thin"),
+            Lto::Fat => write!(f, "fat"),
         }
+    }
+}
+
+/// Parses an address given in hex with a `0x` prefix or in decimal
+fn parse_address(s: &str) -> Result<u64, String> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("{e}"))
+}
+
+fn parse_percentile(s: &str) -> Result<f64, String> {
+    let p: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    if p > 0.0 && p <= 100.0 {
+        Ok(p)
     } else {
-        "".to_string()
+        Err(String::from("must be greater than 0 and at most 100"))
+    }
+}
+
+/// An error with a kind for the error object `--format json` prints
+#[derive(Debug)]
+pub struct Failure {
+    /// `build` or `check`, other errors are `io` or `error`
+    kind: &'static str,
+    message: String,
+}
+
+impl Failure {
+    pub fn build(message: impl Into<String>) -> Failure {
+        Failure {
+            kind: "build",
+            message: message.into(),
+        }
+    }
+
+    /// A failed `--fail-*` check, the report is complete then
+    pub fn check(message: impl Into<String>) -> Failure {
+        Failure {
+            kind: "check",
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Exits with 1 if a check failed, with `--format json` other errors exit with 2 and print an error object
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let format = args.format;
+    let Err(err) = run(args) else {
+        return ExitCode::SUCCESS;
     };
 
-    let mut tmp_file = std::env::temp_dir();
-    let tmp_dir = tmp_file.to_owned();
-    let tmp_dir = tmp_dir.to_str().unwrap().replace("\\", "/");
-    tmp_file.push("2374972342390lnk.x");
-    std::fs::write(
-        &tmp_file,
-        "
-    SECTIONS
+    let failure = err.chain().find_map(|e| e.downcast_ref::<Failure>());
+    if format != Format::Json || failure.is_some_and(|f| f.kind == "check") {
+        eprintln!("Error: {err:?}");
+        return ExitCode::FAILURE;
+    }
+
+    let kind = match failure {
+        Some(failure) => failure.kind,
+        None if err.chain().any(|e| e.is::<std::io::Error>()) => "io",
+        None => "error",
+    };
+    let error = json::Value::Object(vec![
+        ("kind".to_string(), json::Value::String(kind.to_string())),
+        (
+            "message".to_string(),
+            json::Value::String(format!("{err:#}")),
+        ),
+    ]);
+    println!(
+        "{}",
+        json::Value::Object(vec![("error".to_string(), error)]).to_pretty_string()
+    );
+    ExitCode::from(2)
+}
+
+fn run(mut args: Args) -> anyhow::Result<()> {
+    // `RUST_LOG` still works, `-v` just raises the level of our own messages
+    let mut logger = env_logger::Builder::from_default_env();
+    match args.verbose {
+        0 => (),
+        1 => {
+            logger.filter_module("ssize", LevelFilter::Info);
+        }
+        2 => {
+            logger.filter_module("ssize", LevelFilter::Debug);
+        }
+        _ => {
+            logger.filter_module("ssize", LevelFilter::Trace);
+        }
+    }
+    logger.init();
+
+    preset::apply(&mut args)?;
+
+    // `--workspace` builds from the member directories, so a relative path wouldn't be found there
+    if let Some(spec) = &args.target_json {
+        args.target_json = Some(spec.canonicalize().with_context(|| {
+            format!("Failed to find the target specification {}", spec.display())
+        })?);
+    }
+
+    if args.percentile.is_some() && args.sort == SortBy::Ratio {
+        bail!("--percentile needs a size to sum up, use it with --sort stack or --sort code");
+    }
+
+    // cargo, the doctor's probes and the toolchain version check all honor `RUSTC`
+    if let Some(rustc) = &args.rustc {
+        std::env::set_var("RUSTC", rustc);
+    }
+
+    if args.round_trip && (args.format != Format::Json || args.summary_only) {
+        bail!("--round-trip checks the JSON document, use it with --format json");
+    }
+
+    if args.count_only
+        && !matches!(
+            args.format,
+            Format::Table | Format::Json | Format::Toml | Format::Plist
+        )
+    {
+        bail!("--count-only prints a table, JSON, TOML or a property list");
+    }
+
+    if args.summary_only
+        && !matches!(
+            args.format,
+            Format::Table | Format::Json | Format::Toml | Format::Plist
+        )
     {
-      /* `INFO` makes the section not allocatable so it won't be loaded into memory */
-      .stack_sizes (INFO) :
-      {
-        KEEP(*(.stack_sizes));
-      }
-    }    
-    ",
-    )?;
-
-    let mut cargo_args: Vec<String> = Vec::new();
-    cargo_args.push(String::from("--config"));
-    cargo_args.push(String::from(&format!("target.{target}.rustflags=[{rustflags} \"-Z\", \"emit-stack-sizes\",\"-C\", \"link-arg=-T2374972342390lnk.x\",  \"-C\", \"link-arg=-L{}\"]", tmp_dir)));
-    cargo_args.push(String::from("build"));
-    cargo_args.push(String::from("--release"));
-
-    if args.all_features {
-        cargo_args.push(String::from("--all-features"));
-    } else if let Some(features) = &args.features {
-        cargo_args.push(format!("--features={}", features));
-    }
-
-    let file = match (&args.example, &args.bin) {
-        (Some(f), None) => f,
-        (None, Some(f)) => f,
-        _ => bail!("Please specify either --example <NAME> or --bin <NAME>."),
+        bail!("--summary-only prints a table, JSON, TOML or a property list");
+    }
+
+    if let Some(pattern) = &args.group_by_regex {
+        if pattern.captures_len() < 2 {
+            bail!("--group-by-regex needs a capture group, its match is the name of the group");
+        }
+    }
+
+    if args.doctor {
+        return doctor::run(&args);
+    }
+
+    if args.verify_rustc {
+        doctor::verify_rustc(&args)?;
+    }
+
+    if args.watch {
+        return watch::run(&args);
+    }
+
+    if let Some(feature) = &args.compare_features {
+        return compare::features(&args, feature);
+    }
+
+    if let [a, b] = args.compare_toolchains.as_slice() {
+        return compare::toolchains(&args, a, b);
+    }
+
+    if args.list_targets {
+        return cargo::list_targets(&args);
+    }
+
+    let elfs = if args.all_targets {
+        cargo::build_all_targets(&args)?
+    } else if args.workspace {
+        cargo::build_workspace(&args)?
+    } else if args.elf.is_empty() {
+        vec![cargo::build_artifact(&args)?]
+    } else {
+        args.elf
+            .iter()
+            .map(|path| {
+                if path.as_os_str() == "-" {
+                    let mut elf = Vec::new();
+                    std::io::stdin()
+                        .lock()
+                        .read_to_end(&mut elf)
+                        .context("Failed to read the ELF from stdin")?;
+                    return Ok(Build {
+                        path: PathBuf::from("<stdin>"),
+                        elf: elf.into(),
+                        notes: Vec::new(),
+                    });
+                }
+
+                Ok(Build {
+                    path: path.clone(),
+                    elf: ElfData::load(path)
+                        .with_context(|| format!("Failed to read {}", path.display()))?,
+                    notes: Vec::new(),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
     };
 
-    if args.example.is_some() {
-        cargo_args.push(format!("--example={}", file));
+    if args.count_only {
+        return count_all(&args, &elfs);
     }
 
-    if args.bin.is_some() {
-        cargo_args.push(format!("--bin={}", file));
+    report_all(&args, &elfs)
+}
+
+/// Prints the numbers of functions and sections summed over the ELFs, the output of `--count-only`
+fn count_all(args: &Args, elfs: &[Build]) -> anyhow::Result<()> {
+    let map = match &args.map {
+        Some(path) => MapFile::load(path)?,
+        None => MapFile::default(),
+    };
+
+    let mut counts = Counts::default();
+    for Build { path, elf, .. } in elfs {
+        analysis::check_plausible(elf)
+            .with_context(|| format!("{} isn't a valid ELF file", path.display()))?;
+        let functions = analysis::analyze_with_symbols(
+            elf,
+            !args.no_thumb_fixup,
+            &map.symbols,
+            args.relocate_base.unwrap_or(0),
+            args.max_frame,
+        )
+        .with_context(|| format!("Failed to analyze {}", path.display()))?;
+        counts.add(&functions, analysis::allocatable_sections(elf)?);
+    }
+
+    match args.format {
+        Format::Json => println!("{}", counts.to_json().to_pretty_string()),
+        Format::Plist => print!("{}", report::plist(&counts.to_json())),
+        Format::Toml => print!("{}", toml::to_string(&counts)?),
+        _ => counts.print(),
+    }
+    Ok(())
+}
+
+/// Analyzes the ELF at `path` with the symbols of the `--map` added, `--relocate-base` applied and the stack
+/// sizes missing estimated with `--from-cfi`
+fn analyze<'a>(
+    args: &Args,
+    path: &Path,
+    elf: &'a [u8],
+    map: &'a MapFile,
+) -> anyhow::Result<analysis::Functions<'a>> {
+    let mut functions = analysis::analyze_with_symbols(
+        elf,
+        !args.no_thumb_fixup,
+        &map.symbols,
+        args.relocate_base.unwrap_or(0),
+        args.max_frame,
+    )
+    .with_context(|| format!("Failed to analyze {}", path.display()))?;
+    if args.names_from_debug {
+        let named = functions.names_from_debug(elf, !args.no_thumb_fixup)?;
+        info!(
+            "{}: named {named} functions after the debug information",
+            path.display()
+        );
+    }
+    if args.from_cfi {
+        let estimated = functions.estimate_from_cfi(elf, !args.no_thumb_fixup)?;
+        info!(
+            "{}: estimated the stack size of {estimated} functions from the call frame information",
+            path.display()
+        );
+    }
+    Ok(functions)
+}
+
+/// Turns the functions into report rows, with their stack sizes aligned as the ABI requires or
+/// `--abi-normalize`d and `--quiet-zero` and `--exclude-section` applied
+///
+/// Shared by the report and the builds compared by `--compare-features`, `--compare-toolchains` and `--watch`.
+fn processed_rows(
+    args: &Args,
+    path: &Path,
+    elf: &[u8],
+    functions: &analysis::Functions,
+) -> anyhow::Result<Vec<Row>> {
+    let mut rows = report::rows(args, functions);
+    if let Some(intrinsics) = IntrinsicStacks::of(args.intrinsic_stacks.as_deref())? {
+        let applied = intrinsics.apply(&mut rows, &functions.undefined);
+        info!(
+            "{}: assumed the stack size of {applied} intrinsics",
+            path.display()
+        );
+    }
+    let abi = Abi::of(&ElfFile::new(elf).map_err(anyhow::Error::msg)?);
+    if args.align {
+        for stack in rows.iter_mut().filter_map(|row| row.stack.as_mut()) {
+            *stack = abi.align(*stack);
+        }
+    }
+    if args.abi_normalize {
+        for stack in rows.iter_mut().filter_map(|row| row.stack.as_mut()) {
+            *stack += abi.return_address;
+        }
+    }
+    if args.quiet_zero {
+        let before = rows.len();
+        rows.retain(|row| row.code != 0 || row.stack.is_some());
+        if rows.len() < before {
+            info!(
+                "{}: left out {} symbols without code or stack size",
+                path.display(),
+                before - rows.len()
+            );
+        }
     }
+    if !args.exclude_section.is_empty() {
+        let sections = analysis::code_sections(elf)?;
+        let functions = |rows: &[Row]| rows.iter().filter(|row| !row.alias).count();
+        let before = functions(&rows);
+        rows.retain(|row| {
+            let section = analysis::section_of(&sections, row.address).unwrap_or("");
+            !args.exclude_section.iter().any(|excluded| {
+                section
+                    .strip_prefix(excluded.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+            })
+        });
+        eprintln!(
+            "{}: excluded {} functions in {}",
+            path.display(),
+            before - functions(&rows),
+            args.exclude_section.join(", ")
+        );
+    }
+    Ok(rows)
+}
 
-    let cargo_res = Command::new("cargo").args(&cargo_args[..]).status();
+/// Analyzes the ELFs and prints the report
+fn report_all(args: &Args, elfs: &[Build]) -> anyhow::Result<()> {
+    // machine readable formats always put all functions into one document
+    let structured = args.format != Format::Table;
+    let separate = elfs.len() > 1 && !args.combined && !structured && !args.summary_only;
 
-    std::fs::remove_file(&tmp_file)?;
+    let mut notes: Vec<String> = Vec::new();
+    for Build {
+        path,
+        elf,
+        notes: build_notes,
+    } in elfs
+    {
+        let elf = analysis::check_plausible(elf)
+            .and_then(|()| ElfFile::new(elf).map_err(anyhow::Error::msg))
+            .with_context(|| format!("{} isn't a valid ELF file", path.display()))?;
+        let abi = Abi::of(&elf);
+        let alignment = abi.alignment_note().filter(|_| args.align);
+        let abi = abi.note(args.abi_normalize);
+        let cfi = args.from_cfi.then(|| {
+            "Stack sizes marked with `~` are estimates from the call frame information".to_string()
+        });
+        let intrinsics = args.intrinsic_stacks.as_ref().map(|path| {
+            format!(
+                "Stack sizes of intrinsics marked with `~` are assumed by --intrinsic-stacks {}",
+                path.display()
+            )
+        });
+        let startup = analysis::startup(&elf).map(|startup| {
+            if elfs.len() > 1 {
+                format!("{}: {startup}", report::shown_path(args, path))
+            } else {
+                startup
+            }
+        });
+        for note in build_notes
+            .iter()
+            .chain([&abi])
+            .chain(&alignment)
+            .chain(&startup)
+            .chain(&cfi)
+            .chain(&intrinsics)
+        {
+            if !notes.contains(note) {
+                notes.push(note.clone());
+            }
+        }
+    }
+    if !structured {
+        for note in &notes {
+            println!("{note}");
+        }
+    }
 
-    cargo_res?;
+    let ignore = match &args.ignore_file {
+        Some(path) => IgnoreList::load(path)?,
+        None => IgnoreList::default(),
+    };
 
-    let mut path: PathBuf = if let Some(binary) = args.out_override {
-        binary
-    } else if args.example.is_some() {
-        project.path(
-            Artifact::Example(&file),
-            Profile::Release,
-            Some(target),
-            &host,
-        )?
+    let allowed_undefined = match &args.allow_undefined {
+        Some(path) => IgnoreList::load(path)?,
+        None => IgnoreList::default(),
+    };
+    let intrinsics = IntrinsicStacks::of(args.intrinsic_stacks.as_deref())?.unwrap_or_default();
+
+    let crates = if args.deps_only || args.flag_dynamic || args.by_local_crate {
+        let platform = args
+            .assume_target
+            .as_deref()
+            .filter(|_| !args.elf.is_empty());
+        match Crates::query(&cargo::lock_flags(args), platform) {
+            Ok(crates) => crates,
+            // analyzing ELFs doesn't need a cargo project
+            Err(e) if !args.elf.is_empty() => {
+                eprintln!(
+                    "{e:#}, taking the ELF file names as the workspace crates and every other crate as a dependency"
+                );
+                Crates::from_files(&args.elf)
+            }
+            Err(e) => return Err(e),
+        }
     } else {
-        project.path(Artifact::Bin(&file), Profile::Release, Some(target), &host)?
+        Crates::default()
     };
 
-    // the project crate seems to have problems with workspaces (at least on Windows) ... if the file isn't there let's guess one level up
-    // otherwise the user can still specify `out_override`
-    if !path.exists() {
-        let mut parts: Vec<Component> = path.components().collect();
-        let target_index = parts
+    let baseline = args.baseline.as_deref().map(Snapshot::load).transpose()?;
+    let baseline_sections = baseline.as_ref().and_then(|b| b.sections);
+    if baseline.is_some() && baseline_sections.is_none() {
+        eprintln!("The baseline doesn't contain section sizes");
+    }
+    let baseline_functions = baseline
+        .as_ref()
+        .map(|b| b.function_names())
+        .unwrap_or_default();
+    if (args.fail_on_new.is_some() || args.fail_on_regression) && baseline_functions.is_empty() {
+        bail!("The baseline doesn't contain any functions, write it again using `--output`");
+    }
+
+    let map = match &args.map {
+        Some(path) => MapFile::load(path)?,
+        None => MapFile::default(),
+    };
+    info!("{} symbols in the map file", map.symbols.len());
+
+    // with multiple ELFs the section totals are summed up
+    let mut sections = Sections::default();
+    let mut combined = Vec::new();
+    let mut address_width = 8;
+    let mut budget = BudgetReport::new(args.fail_over, args.fail_over_total);
+    let mut new_over_budget = Vec::new();
+    let mut undefined = BTreeSet::new();
+    let mut function_sizes = Vec::new();
+    let mut per_elf = Vec::new();
+    let mut by_section = BySection::default();
+    let mut worst_case: BTreeMap<String, Row> = BTreeMap::new();
+    for (i, Build { path, elf, .. }) in elfs.iter().enumerate() {
+        let functions = analyze(args, path, elf, &map)?;
+        let address_bits = if functions.have_32_bit_addresses {
+            32
+        } else {
+            64
+        };
+
+        info!(
+            "{}: {} functions, {} undefined symbols, {}-bit addresses",
+            path.display(),
+            functions.defined.len(),
+            functions.undefined.len(),
+            address_bits
+        );
+        info!(
+            "{} `.stack_sizes` entries matched a function, {} didn't",
+            functions.matched,
+            functions.unmatched.len()
+        );
+        for problem in &functions.malformed {
+            eprintln!("{}: malformed `.stack_sizes`: {problem}", path.display());
+        }
+        if functions.defined.is_empty() && !functions.unmatched.is_empty() && args.map.is_none() {
+            eprintln!(
+                "{} has stack sizes but no symbols, it might be stripped - a linker map can be given with `--map`, or the names taken from the debug information with `--names-from-debug`",
+                path.display()
+            );
+        } else if functions.matched == 0 && !functions.unmatched.is_empty() {
+            eprintln!(
+                "None of the `.stack_sizes` entries of {} matches a function, if their addresses are offset \
+                 consistently `--relocate-base` subtracts the offset",
+                path.display()
+            );
+        }
+        if args.fail_on_undefined {
+            undefined.extend(
+                functions
+                    .undefined
+                    .iter()
+                    .filter(|name| {
+                        !allowed_undefined.matches_name(name) && !intrinsics.contains(name)
+                    })
+                    .map(|name| rustc_demangle::demangle(name).to_string()),
+            );
+        }
+        if args.no_thumb_fixup {
+            eprintln!(
+                "{}: {} `.stack_sizes` entries matched a function exactly, {} didn't",
+                path.display(),
+                functions.matched,
+                functions.unmatched.len()
+            );
+        }
+        if args.dump_stack_sizes {
+            print_extra(
+                structured,
+                &report::stack_sizes_dump(args, path, &functions.entries, address_bits / 4),
+            );
+        }
+        for (address, stack) in &functions.unmatched {
+            trace!("Unmatched `.stack_sizes` entry: address {address:#x}, stack {stack}");
+        }
+        for (address, function) in &functions.defined {
+            // `size` already falls back to the extent, compare against what the symbol claims. A few bytes of
+            // alignment padding or overlap between functions are expected
+            let symbol_size = function.symbol_size();
+            if let Some(extent) = function.extent() {
+                if (symbol_size == 0 && extent > 0) || extent.abs_diff(symbol_size) > 64 {
+                    info!(
+                        "Symbol size {symbol_size} of the function at {address:#x} differs from its extent {extent}"
+                    );
+                }
+            }
+        }
+
+        let width = address_bits / 4;
+        let mut rows = processed_rows(args, path, elf, &functions)?;
+        let sizes: Vec<_> = rows
             .iter()
-            .position(|c| match c {
-                Component::Normal(name) if name.to_str() == Some("target") => true,
-                _ => false,
-            })
-            .unwrap_or(usize::MAX);
+            .filter(|row| !row.alias)
+            .map(FunctionSize::of)
+            .collect();
+        function_sizes.extend(sizes.iter().cloned());
+        if args.by_section {
+            by_section.add(&analysis::code_sections(elf)?, &rows);
+        }
+        // budgets apply to all functions, `--deps-only` and `--hide-ignored` only affect what's shown
+        budget.check(
+            rows.iter()
+                .filter(|row| !row.alias && !ignore.matches(row))
+                .map(|row| (row.names.join(" "), row.stack.unwrap_or(0))),
+        );
+        if let Some(limit) = args.fail_on_new {
+            new_over_budget.extend(
+                rows.iter()
+                    .filter(|row| {
+                        !row.alias
+                            && row.stack.unwrap_or(0) > limit
+                            && !ignore.matches(row)
+                            && !row
+                                .names
+                                .iter()
+                                .any(|n| baseline_functions.contains(&report::comparable(n)))
+                    })
+                    .map(|row| (row.names.join(" "), row.stack.unwrap_or(0))),
+            );
+        }
+
+        if args.deps_only {
+            rows.retain(|row| {
+                row.names
+                    .iter()
+                    .filter_map(|n| crates::crate_of(n))
+                    .any(|krate| crates.is_dependency(krate))
+            });
+        }
+        if args.hide_ignored {
+            rows.retain(|row| !ignore.matches(row));
+        }
+
+        // machine readable formats keep the closures
+        if args.collapse_closures && !structured {
+            rows = report::collapse_closures(rows);
+        }
 
-        if target_index != usize::MAX && target_index > 0 {
-            parts.remove(target_index - 1);
+        if args.all_targets || args.workspace {
+            for row in rows.iter().filter(|row| !row.alias) {
+                let key = row
+                    .names
+                    .iter()
+                    .map(|n| report::without_hashes(n))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                match worst_case.get(&key) {
+                    Some(worst) if worst.stack >= row.stack => (),
+                    _ => {
+                        worst_case.insert(key, row.clone());
+                    }
+                }
+            }
+        }
 
-            let mut tmp = PathBuf::new();
-            for c in parts {
-                tmp.push(c);
+        if separate {
+            if i > 0 {
+                println!();
             }
+            println!("{}:", report::shown_path(args, path));
+            report::print(args, rows, width);
+        } else {
+            address_width = address_width.max(width);
+            combined.extend(rows);
+        }
 
-            path = tmp;
+        let elf_sections = Sections::of(&ElfFile::new(elf).map_err(anyhow::Error::msg)?);
+        info!(
+            "Sections: .text {} .rodata {} .data {} .bss {}",
+            elf_sections.text, elf_sections.rodata, elf_sections.data, elf_sections.bss
+        );
+        sections += elf_sections;
+        if args.output_dir.is_some() {
+            per_elf.push((
+                path,
+                Snapshot {
+                    sections: Some(elf_sections),
+                    functions: sizes,
+                },
+            ));
         }
     }
 
-    let elf = std::fs::read(path)?;
-    let functions = stack_sizes::analyze_executable(&elf)?;
+    // the frames of all functions, not a path through the call graph
+    let over_total = budget.over_total();
+    let baseline_sizes = baseline
+        .as_ref()
+        .map(|b| b.functions.as_slice())
+        .unwrap_or_default();
+    let regressions: Vec<_> =
+        snapshot::regressions(&function_sizes, baseline_sizes, args.delta_threshold)
+            .into_iter()
+            .filter(|(name, ..)| !ignore.matches_name(name))
+            .map(|(name, base, stack)| (name.to_string(), base, stack))
+            .collect();
 
-    let mut functions: Vec<(String, u64, u64)> = functions
-        .defined
-        .iter()
-        .map(|(_, f)| {
-            let mut fname = String::new();
-            for name in f.names() {
-                if name.len() > 0 {
-                    fname.push_str(&format!("{} ", rustc_demangle::demangle(name)));
+    match args.format {
+        Format::Table if args.summary_only => {
+            Summary::of(args, combined, budget.over.len()).print();
+        }
+        Format::Json if args.summary_only => {
+            let summary = Summary::of(args, combined, budget.over.len());
+            println!("{}", summary.to_json().to_pretty_string());
+        }
+        Format::Plist if args.summary_only => {
+            let summary = Summary::of(args, combined, budget.over.len());
+            print!("{}", report::plist(&summary.to_json()));
+        }
+        Format::Toml if args.summary_only => {
+            let summary = Summary::of(args, combined, budget.over.len());
+            print!("{}", toml::to_string(&summary)?);
+        }
+        Format::Table => {
+            if !separate {
+                report::print(args, combined, address_width);
+            }
+            if args.all_targets || args.workspace {
+                println!();
+                if args.workspace {
+                    println!("Worst case of all packages:");
+                } else {
+                    println!("Worst case of all targets:");
                 }
+                report::print(args, worst_case.into_values().collect(), address_width);
+            }
+            if let Some(baseline) = &baseline_sections {
+                snapshot::print_section_diff(&sections, baseline);
+            }
+            if !baseline_sizes.is_empty() {
+                snapshot::print_function_diff(
+                    &function_sizes,
+                    baseline_sizes,
+                    args.delta_threshold,
+                );
+            }
+        }
+        Format::Json | Format::Toml | Format::Csv | Format::Plist => {
+            let document = report::document(
+                args,
+                elfs,
+                notes,
+                combined,
+                sections,
+                baseline_sections,
+                address_width,
+            );
+            let document = if args.by_local_crate {
+                document.with_local_crates(report::crate_groups(&function_sizes, &crates))
+            } else {
+                document
+            };
+            if args.round_trip {
+                document.check_round_trip()?;
+            }
+            match args.format {
+                Format::Json => println!("{}", document.to_json().to_pretty_string()),
+                Format::Csv => print!("{}", document.to_csv()),
+                Format::Plist => print!("{}", report::plist(&document.to_json())),
+                _ => print!("{}", toml::to_string(&document)?),
+            }
+        }
+        Format::Binary => {
+            let snapshot = Snapshot {
+                sections: Some(sections),
+                functions: function_sizes.clone(),
+            };
+            std::io::stdout().write_all(&snapshot.to_binary())?;
+        }
+        Format::RustConst => print!("{}", report::rust_const(args, combined)?),
+        Format::Prometheus => print!("{}", report::prometheus(args, combined, &sections)),
+        Format::Junit => print!(
+            "{}",
+            report::junit(&[
+                BudgetCheck {
+                    name: "fail-over",
+                    limit: args.fail_over,
+                    over: &budget.over,
+                },
+                BudgetCheck {
+                    name: "fail-on-new",
+                    limit: args.fail_on_new,
+                    over: &new_over_budget,
+                },
+                BudgetCheck {
+                    name: "fail-over-total",
+                    limit: args.fail_over_total,
+                    over: &over_total,
+                },
+            ])
+        ),
+    }
+
+    if let Some(ratio) = args.stack_code_ratio {
+        print_extra(structured, &report::suspicious(&function_sizes, ratio));
+    }
+
+    if args.flag_dynamic {
+        print_extra(structured, &report::dynamic(&function_sizes, &crates));
+    }
+
+    if args.histogram {
+        print_extra(
+            structured,
+            &report::histogram(&function_sizes, &args.buckets),
+        );
+    }
+
+    if args.by_section {
+        print_extra(structured, &by_section.format());
+    }
+
+    // JSON and TOML documents carry the groups
+    let in_document =
+        matches!(args.format, Format::Json | Format::Toml | Format::Plist) && !args.summary_only;
+    if args.by_local_crate && !in_document {
+        print_extra(
+            structured,
+            &report::by_local_crate(&report::crate_groups(&function_sizes, &crates)),
+        );
+    }
+
+    if let Some(pattern) = &args.group_by_regex {
+        print_extra(
+            structured,
+            &report::by_regex(&report::regex_groups(&function_sizes, pattern)),
+        );
+    }
+
+    if args.highlight_fmt {
+        print_extra(structured, &report::fmt_summary(&function_sizes));
+    }
+
+    if let Some(observed) = &args.observed {
+        print_extra(
+            structured,
+            &Observed::load(observed)?.compare(&function_sizes),
+        );
+    }
+
+    if let Some(output) = &args.output {
+        Snapshot {
+            sections: Some(sections),
+            functions: function_sizes,
+        }
+        .save(output)?;
+    }
+
+    if let Some(dir) = &args.output_dir {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let mut written = Vec::new();
+        for (path, snapshot) in per_elf {
+            let stem = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .filter(|name| name != "<stdin>")
+                .unwrap_or_else(|| String::from("stdin"));
+            // the same executable name can show up in several packages or directories
+            let mut file = dir.join(format!("{stem}.toml"));
+            let mut n = 2;
+            while written.contains(&file) {
+                file = dir.join(format!("{stem}-{n}.toml"));
+                n += 1;
             }
-            (fname, f.size(), f.stack().unwrap_or(0))
-        })
-        .collect();
+            snapshot.save(&file)?;
+            written.push(file);
+        }
+        eprintln!("Wrote {} snapshots:", written.len());
+        for file in &written {
+            eprintln!("  {}", file.display());
+        }
+    }
 
-    functions.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap());
+    if let Some(limit) = budget.per_function {
+        if !budget.over.is_empty() {
+            eprintln!();
+            for (name, stack) in &budget.over {
+                eprintln!("{stack:5} {name}");
+            }
+            bail!(Failure::check(format!(
+                "{} functions need more than {limit} bytes of stack",
+                budget.over.len()
+            )));
+        }
+    }
 
-    let min_stack = args.min_stack.unwrap_or(0);
+    if let Some(limit) = budget.total_limit {
+        if !over_total.is_empty() {
+            bail!(Failure::check(format!(
+                "The stack frames of all functions add up to {} bytes, more than {limit} bytes",
+                budget.total
+            )));
+        }
+    }
 
-    println!("Code  Stack Name");
-    for (name, code_size, stack_size) in functions
-        .iter()
-        .filter(|(_name, _code_size, stack_size)| stack_size >= &min_stack)
-    {
-        println!("{:5} {:5} {}", code_size, stack_size, name);
+    if let Some(limit) = args.fail_on_new {
+        if !new_over_budget.is_empty() {
+            eprintln!();
+            for (name, stack) in &new_over_budget {
+                eprintln!("{stack:5} {name} (new)");
+            }
+            bail!(Failure::check(format!(
+                "{} functions not in the baseline need more than {limit} bytes of stack",
+                new_over_budget.len()
+            )));
+        }
+    }
+
+    if args.fail_on_regression && !regressions.is_empty() {
+        eprintln!();
+        for (name, base, stack) in &regressions {
+            eprintln!("{stack:5} {name} (was {base})");
+        }
+        bail!(Failure::check(format!(
+            "{} functions need more stack than in the baseline",
+            regressions.len()
+        )));
+    }
+
+    if !undefined.is_empty() {
+        eprintln!();
+        for name in &undefined {
+            eprintln!("{name}");
+        }
+        bail!(Failure::check(format!(
+            "{} undefined symbols are referenced",
+            undefined.len()
+        )));
     }
 
     Ok(())
 }
+
+/// Prints a section following the report, to stderr if that would break a machine readable format
+fn print_extra(structured: bool, text: &str) {
+    if structured {
+        eprint!("{text}");
+    } else {
+        print!("{text}");
+    }
+}
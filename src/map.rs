@@ -0,0 +1,267 @@
+use std::path::Path;
+
+use anyhow::{bail, Context};
+
+/// Symbols read from a linker map file given with `--map`, to analyze stripped executables
+///
+/// GNU ld only lists global symbols, the local functions of Rust code are found through their input sections,
+/// `-Z function-sections` (the default) places every function in a `.text.<symbol>` section of its own.
+/// LLD lists every symbol, demangled.
+#[derive(Debug, Default)]
+pub struct MapFile {
+    pub symbols: Vec<MapSymbol>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MapSymbol {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+}
+
+/// A symbol before its size is known, `end` bounds it to its input section
+struct Pending {
+    name: String,
+    address: u64,
+    size: u64,
+    end: u64,
+}
+
+impl MapFile {
+    pub fn load(path: &Path) -> anyhow::Result<MapFile> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        let pending = if let Some(header) = content.lines().find(|line| is_lld_header(line)) {
+            parse_lld(&content, header)
+        } else if content.contains("Linker script and memory map") {
+            parse_gnu(&content)
+        } else {
+            bail!("{} isn't a GNU ld or LLD map file", path.display());
+        };
+
+        Ok(MapFile {
+            symbols: with_sizes(pending),
+        })
+    }
+}
+
+/// The column headers of an LLD map, `VMA LMA Size Align Out In Symbol` or without the `LMA` in older versions
+fn is_lld_header(line: &str) -> bool {
+    let columns: Vec<&str> = line.split_whitespace().collect();
+    matches!(
+        columns.as_slice(),
+        ["VMA", "LMA", "Size", "Align", "Out", "In", "Symbol"]
+            | ["Address", "Size", "Align", "Out", "In", "Symbol"]
+    )
+}
+
+fn parse_lld(content: &str, header: &str) -> Vec<Pending> {
+    let columns: Vec<&str> = header.split_whitespace().collect();
+    let numbers = columns.iter().position(|&c| c == "Out").unwrap_or(0);
+    let size_column = columns.iter().position(|&c| c == "Size").unwrap_or(0);
+    let symbol_column = header.find("Symbol").unwrap_or(usize::MAX);
+    let in_column = header.find(" In ").map(|i| i + 1).unwrap_or(usize::MAX);
+
+    let mut symbols = Vec::new();
+    let mut section_end = 0;
+    for line in content.lines() {
+        let mut rest = line;
+        let mut values = Vec::with_capacity(numbers);
+        for _ in 0..numbers {
+            let trimmed = rest.trim_start();
+            let end = trimmed.find(' ').unwrap_or(trimmed.len());
+            let Ok(value) = u64::from_str_radix(&trimmed[..end], 16) else {
+                break;
+            };
+            values.push(value);
+            rest = &trimmed[end..];
+        }
+        if values.len() != numbers {
+            continue;
+        }
+
+        // the indentation tells output sections, input sections and symbols apart
+        let text = rest.trim_start();
+        let column = line.len() - text.len();
+        let (address, size) = (values[0], values[size_column]);
+        if column >= symbol_column {
+            symbols.push(Pending {
+                name: text.trim_end().to_string(),
+                address,
+                size,
+                end: section_end,
+            });
+        } else if column >= in_column {
+            section_end = address + size;
+        }
+    }
+    symbols
+}
+
+fn parse_gnu(content: &str) -> Vec<Pending> {
+    let mut symbols = Vec::new();
+    let mut section: Option<&str> = None;
+    let mut section_end = 0;
+    // the part before lists the discarded input sections
+    let memory_map = content
+        .lines()
+        .skip_while(|line| !line.starts_with("Linker script and memory map"));
+    for line in memory_map {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let hex = |token: Option<&&str>| {
+            token
+                .and_then(|t| t.strip_prefix("0x"))
+                .and_then(|t| u64::from_str_radix(t, 16).ok())
+        };
+
+        // input sections are indented by one space, long names wrap the address and size to the next line
+        let (name, numbers) = if line.starts_with(" .") {
+            (Some(tokens[0]), &tokens[1..])
+        } else if line.starts_with("  ") && section.is_some() && tokens.len() >= 2 {
+            (section.take(), &tokens[..])
+        } else {
+            if let (2, Some(address)) = (tokens.len(), hex(tokens.first())) {
+                if line.starts_with("  ") && !tokens[1].contains(['=', '(']) {
+                    symbols.push(Pending {
+                        name: tokens[1].to_string(),
+                        address,
+                        size: 0,
+                        end: section_end,
+                    });
+                }
+            }
+            continue;
+        };
+
+        let (Some(address), Some(size)) = (hex(numbers.first()), hex(numbers.get(1))) else {
+            section = name;
+            continue;
+        };
+        section_end = address + size;
+        let Some(name) = name else {
+            continue;
+        };
+        let symbol = name
+            .strip_prefix(".text.unlikely.")
+            .or_else(|| name.strip_prefix(".text."));
+        if let Some(symbol) = symbol.filter(|_| size > 0) {
+            symbols.push(Pending {
+                name: symbol.to_string(),
+                address,
+                size,
+                end: section_end,
+            });
+        }
+    }
+    symbols
+}
+
+/// Symbols without a size reach up to the next symbol or the end of their input section
+fn with_sizes(mut pending: Vec<Pending>) -> Vec<MapSymbol> {
+    pending.sort_by_key(|p| p.address);
+    let starts: Vec<u64> = pending.iter().map(|p| p.address).collect();
+    pending
+        .into_iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let size = if p.size > 0 {
+                p.size
+            } else {
+                let next = starts[i..]
+                    .iter()
+                    .find(|&&start| start > p.address)
+                    .copied()
+                    .unwrap_or(p.end);
+                next.min(p.end).saturating_sub(p.address)
+            };
+            MapSymbol {
+                name: p.name,
+                address: p.address,
+                size,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(fixture: &str) -> Vec<(String, u64, u64)> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures")
+            .join(fixture);
+        MapFile::load(&path)
+            .unwrap()
+            .symbols
+            .into_iter()
+            .map(|s| (s.name, s.address, s.size))
+            .collect()
+    }
+
+    fn symbols(expected: &[(&str, u64, u64)]) -> Vec<(String, u64, u64)> {
+        expected
+            .iter()
+            .map(|&(name, address, size)| (name.to_string(), address, size))
+            .collect()
+    }
+
+    #[test]
+    fn parses_gnu_ld_maps() {
+        // sections of functions and the symbols GNU ld lists in them come at the same address, symbols without a
+        // size reach up to the next one or the end of their input section
+        assert_eq!(
+            load("gnu.map"),
+            symbols(&[
+                (
+                    "_RNvNtCsgEmfK2I1SDS_4core6option13unwrap_failed",
+                    0xa200,
+                    0x19
+                ),
+                ("core::option::unwrap_failed", 0xa200, 0x19),
+                ("_start", 0xa220, 0x22),
+                (
+                    "_ZN1r38helper_with_a_rather_long_name_to_wrap17h8ffa854e1dd90c1dE",
+                    0xa3d0,
+                    0x13
+                ),
+                ("_ZN1r3Foo3new17h6f63ae33cf765313E", 0xa3f0, 0x1),
+                ("_ZN1r4main17h086b0cfd890a8ea9E", 0xa400, 0x39),
+                ("r::main", 0xa400, 0x39),
+                ("memcpy", 0xa440, 0x40),
+                ("memmove", 0xa480, 0x50),
+                ("_fini", 0xa4d0, 0x9),
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_lld_maps() {
+        // LLD lists demangled names, spaces included
+        assert_eq!(
+            load("lld.map"),
+            symbols(&[
+                ("_start", 0x14250, 0x22),
+                ("std::rt::lang_start::<()>::{closure#0}", 0x14790, 0x13),
+                (
+                    "<std::rt::lang_start<()>::{closure#0} as core::ops::function::FnOnce<()>>::call_once::{shim:vtable#0}",
+                    0x14860,
+                    0xb
+                ),
+                ("r::helper_with_a_rather_long_name_to_wrap", 0x14870, 0x13),
+                ("r::main", 0x14890, 0x39),
+                ("<r::Foo>::new", 0x148d0, 0x1),
+                ("memcpy", 0x148e0, 0x8),
+                ("memmove", 0x148e8, 0x8),
+                ("_fini", 0x148f0, 0x9),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/functions.s");
+        assert!(MapFile::load(&path).is_err());
+    }
+}
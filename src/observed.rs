@@ -0,0 +1,76 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{bail, Context};
+
+use crate::{report, snapshot::FunctionSize};
+
+/// Stack usage measured at runtime, read from an `--observed` file
+///
+/// Each line holds a symbol (mangled or demangled) and the observed number of bytes, `#` starts a comment.
+pub struct Observed {
+    entries: Vec<(String, u64)>,
+}
+
+impl Observed {
+    pub fn load(path: &Path) -> anyhow::Result<Observed> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        let mut entries = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split_once('#').map(|(l, _)| l).unwrap_or(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            // names of trait impls contain spaces, the number is the last field
+            let Some((name, bytes)) = line.rsplit_once(char::is_whitespace) else {
+                bail!(
+                    "{}:{}: expected a symbol and a number of bytes",
+                    path.display(),
+                    i + 1
+                );
+            };
+            let bytes = bytes.parse().with_context(|| {
+                format!("{}:{}: invalid number of bytes", path.display(), i + 1)
+            })?;
+            let name = report::without_hashes(&rustc_demangle::demangle(name.trim()).to_string());
+            entries.push((name, bytes));
+        }
+
+        Ok(Observed { entries })
+    }
+
+    /// Formats a table of the observed next to the static stack usage
+    ///
+    /// Functions that used more stack than their static size are flagged, the analysis missed something there.
+    pub fn compare(&self, functions: &[FunctionSize]) -> String {
+        let mut by_name = HashMap::new();
+        for f in functions {
+            for name in std::iter::once(&f.name).chain(&f.aliases) {
+                by_name.entry(report::comparable(name)).or_insert(f.stack);
+            }
+        }
+
+        let mut out = String::from("\nObserved Static Name\n");
+        let mut exceeded = 0;
+        for (name, observed) in &self.entries {
+            let line = match by_name.get(&report::comparable(name)) {
+                Some(Some(stack)) if observed > stack => {
+                    exceeded += 1;
+                    format!("{observed:8} {stack:6} {name} (exceeds static)")
+                }
+                Some(Some(stack)) => format!("{observed:8} {stack:6} {name}"),
+                Some(None) => format!("{observed:8} {:>6} {name} (no stack size)", "-"),
+                None => format!("{observed:8} {:>6} {name} (not found)", "-"),
+            };
+            out.push_str(&line);
+            out.push('\n');
+        }
+        out.push_str(&format!(
+            "{exceeded} of {} observed functions exceed their static stack size\n",
+            self.entries.len()
+        ));
+        out
+    }
+}
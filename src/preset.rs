@@ -0,0 +1,60 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::Args;
+
+/// Contents of `.ssize.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SsizeToml {
+    #[serde(default)]
+    preset: BTreeMap<String, Preset>,
+}
+
+/// Defaults for a board variant or build flavor, selected via `--preset`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Preset {
+    target: Option<String>,
+    features: Option<String>,
+    min_stack: Option<u64>,
+    fail_over: Option<u64>,
+}
+
+/// Fills in the options of `--preset` from `.ssize.toml` which weren't given on the command line
+pub fn apply(args: &mut Args) -> anyhow::Result<()> {
+    let Some(name) = args.preset.clone() else {
+        return Ok(());
+    };
+
+    let path = Path::new(".ssize.toml");
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("--preset needs a {} file", path.display()))?;
+    let mut config: SsizeToml =
+        toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    let Some(preset) = config.preset.remove(&name) else {
+        let known: Vec<_> = config.preset.keys().map(String::as_str).collect();
+        bail!(
+            "There's no preset `{name}` in {}, available: {}",
+            path.display(),
+            known.join(", ")
+        );
+    };
+
+    if args.target.is_none() {
+        args.target = preset.target;
+    }
+    if args.features.is_none() && !args.all_features {
+        args.features = preset.features;
+    }
+    if args.min_stack.is_none() {
+        args.min_stack = preset.min_stack;
+    }
+    if args.fail_over.is_none() {
+        args.fail_over = preset.fail_over;
+    }
+    Ok(())
+}
@@ -0,0 +1,1826 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use anyhow::bail;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{
+    analysis::{self, Functions, StackSizesEntry},
+    cargo::Build,
+    crates::{self, Crates},
+    json,
+    snapshot::{FunctionSize, Sections},
+    Args, Mangling, PreferName, SortBy, StackUnit,
+};
+
+/// A function as shown in the report
+#[derive(Clone)]
+pub struct Row {
+    pub address: u64,
+    /// Demangled name and aliases
+    pub names: Vec<String>,
+    pub code: u64,
+    /// Distance to the next function
+    pub extent: Option<u64>,
+    pub stack: Option<u64>,
+    /// Whether the stack size is an estimate from the call frame information (`--from-cfi`) or assumed by
+    /// `--intrinsic-stacks`
+    pub estimated: bool,
+    /// Another name of the function in the previous row (only with `--split-aliases`)
+    pub alias: bool,
+    /// Number of closures summed up into this row (only with `--collapse-closures`)
+    pub closures: usize,
+}
+
+/// Turns the analyzed functions into report rows in the `--sort` order, demangling their names
+pub fn rows(args: &Args, functions: &Functions) -> Vec<Row> {
+    let functions = match args.sort {
+        SortBy::Code => functions.by_code_desc(),
+        SortBy::Stack | SortBy::Ratio => functions.by_stack_desc(),
+    };
+    functions
+        .into_iter()
+        .flat_map(|(address, f)| {
+            let mut names: Vec<(String, bool)> = f
+                .names()
+                .iter()
+                .filter_map(|name| {
+                    let rust = rustc_demangle::try_demangle(name).is_ok();
+                    display_name(name, args.mangling).map(|n| (n, rust))
+                })
+                .collect();
+            // different symbols can demangle to the same name, keep the Rust one then
+            names.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+            names.dedup_by(|a, b| a.0 == b.0);
+            if let Some(primary) = primary_name(args.prefer_name, &names) {
+                let name = names.remove(primary);
+                names.insert(0, name);
+            }
+            let mut names: Vec<String> = names.into_iter().map(|(name, _)| name).collect();
+            if names.is_empty() {
+                names.push(format!("{address:#x}"));
+            }
+            let row = |names, alias| Row {
+                address,
+                names,
+                code: f.size(),
+                extent: f.extent(),
+                stack: f.stack(),
+                estimated: f.estimated(),
+                alias,
+                closures: 0,
+            };
+
+            if args.split_aliases {
+                names
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, name)| row(vec![name], i > 0))
+                    .collect::<Vec<_>>()
+            } else {
+                vec![row(names, false)]
+            }
+        })
+        .collect()
+}
+
+/// Returns the function a closure is defined in, `None` if `name` isn't a closure
+///
+/// Only markers outside of generic arguments count, `<foo::{closure#0} as FnOnce>::call_once` is a shim.
+fn closure_parent(name: &str) -> Option<&str> {
+    let mut depth = 0i32;
+    for (i, c) in name.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            ':' if depth == 0 => {
+                let rest = &name[i..];
+                if rest.starts_with("::{closure") || rest.starts_with("::{{closure}}") {
+                    return Some(&name[..i]);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Sums up the code and stack of closures into the function they're defined in, for `--collapse-closures`
+///
+/// A parent that isn't in the list (e.g. because it got inlined) gets a row of its own.
+pub fn collapse_closures(rows: Vec<Row>) -> Vec<Row> {
+    let mut collapsed: Vec<Row> = Vec::with_capacity(rows.len());
+    let mut closures: Vec<(String, Row)> = Vec::new();
+    for row in rows {
+        match row.names.first().and_then(|n| closure_parent(n)) {
+            Some(parent) if !row.alias => closures.push((parent.to_string(), row)),
+            _ => collapsed.push(row),
+        }
+    }
+
+    // the first row of each parent collects its closures
+    let mut parents: HashMap<String, usize> = HashMap::new();
+    for (index, row) in collapsed.iter().enumerate().filter(|(_, row)| !row.alias) {
+        if let Some(name) = row.names.first() {
+            parents.entry(name.clone()).or_insert(index);
+        }
+    }
+
+    for (parent, closure) in closures {
+        let index = match parents.get(&parent) {
+            Some(&index) => index,
+            None => {
+                collapsed.push(Row {
+                    names: vec![parent.clone()],
+                    code: 0,
+                    extent: None,
+                    stack: None,
+                    estimated: false,
+                    ..closure.clone()
+                });
+                parents.insert(parent, collapsed.len() - 1);
+                collapsed.len() - 1
+            }
+        };
+        let row = &mut collapsed[index];
+        row.code += closure.code;
+        row.stack = match (row.stack, closure.stack) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+        };
+        row.estimated |= closure.estimated;
+        row.closures += 1;
+    }
+    collapsed
+}
+
+/// Picks the index of the name to show first, the flag tells whether it's a Rust symbol
+fn primary_name(prefer: PreferName, names: &[(String, bool)]) -> Option<usize> {
+    let mut indexed = names.iter().enumerate();
+    match prefer {
+        PreferName::First => None,
+        PreferName::Rust => indexed.find(|(_, (_, rust))| *rust).map(|(i, _)| i),
+        PreferName::Short => indexed
+            .min_by_key(|(_, (name, _))| name.chars().count())
+            .map(|(i, _)| i),
+        // `max_by_key` picks the last of equally long names, prefer the first one
+        PreferName::Long => indexed
+            .max_by_key(|(i, (name, _))| (name.chars().count(), std::cmp::Reverse(*i)))
+            .map(|(i, _)| i),
+    }
+}
+
+/// Demangles `name`, falling back to the raw symbol if that yields nothing printable
+///
+/// With `--mangling legacy` v0 names are rendered in the legacy syntax, with `--mangling v0` legacy names lose their
+/// hash like v0 names don't have one. Returns `None` if neither is usable
+fn display_name(name: &str, mangling: Mangling) -> Option<String> {
+    let mut demangled = rustc_demangle::demangle(name).to_string();
+    // v0 symbols start with `_R`, with an extra underscore on some platforms
+    let v0 = name.starts_with("_R") || name.starts_with("__R");
+    match mangling {
+        Mangling::Legacy if v0 => demangled = comparable(&demangled),
+        Mangling::V0 if !v0 => demangled = without_hashes(&demangled),
+        _ => (),
+    }
+    if !demangled.trim().is_empty() {
+        Some(demangled)
+    } else if !name.trim().is_empty() {
+        Some(name.to_string())
+    } else {
+        None
+    }
+}
+
+/// Removes `[1a2b3c4d5e6f7a8b]` crate disambiguators and `::h1a2b3c4d5e6f7a8b` hashes
+pub fn without_hashes(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(start) = rest.find('[') {
+        let end = rest[start..].find(']').map(|end| start + end);
+        match end {
+            Some(end)
+                if end > start + 1
+                    && rest[start + 1..end].bytes().all(|b| b.is_ascii_hexdigit()) =>
+            {
+                normalized.push_str(&rest[..start]);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                normalized.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    normalized.push_str(rest);
+
+    if let Some((path, hash)) = normalized.rsplit_once("::h") {
+        if hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            normalized.truncate(path.len());
+        }
+    }
+    normalized
+}
+
+/// Brings a demangled name into the legacy syntax without hashes, so names of v0 and legacy mangled builds match
+///
+/// Removes crate disambiguators and hashes, turns `drop_in_place::<T>` into `drop_in_place<T>`,
+/// `<Type>::method` into `Type::method` and `{closure#0}` into `{{closure}}`. Generic arguments of functions are
+/// only part of v0 names, they are kept.
+pub fn comparable(name: &str) -> String {
+    let mut name = without_hashes(name)
+        .replace("::<", "<")
+        .replace(", ", ",")
+        .replace("{shim:vtable#0}", "{{vtable.shim}}")
+        .replace("{shim:reify#0}", "{{reify.shim}}");
+
+    while let Some(start) = name.find("{closure#") {
+        match name[start..].find('}') {
+            Some(end) => name.replace_range(start..start + end + 1, "{{closure}}"),
+            None => break,
+        }
+    }
+
+    // inherent impls are wrapped in angle brackets by v0, trait impls (`<T as Trait>`) are in both
+    if name.starts_with('<') {
+        let mut depth = 0;
+        let mut trait_impl = false;
+        let mut end = None;
+        for (i, c) in name.char_indices() {
+            match c {
+                '<' => depth += 1,
+                '>' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = Some(i);
+                        break;
+                    }
+                }
+                ' ' if depth == 1 && name[i..].starts_with(" as ") => trait_impl = true,
+                _ => (),
+            }
+        }
+        if let Some(end) = end.filter(|_| !trait_impl) {
+            if name[end + 1..].starts_with("::") {
+                name = format!("{}{}", &name[1..end], &name[end + 1..]);
+            }
+        }
+    }
+
+    name
+}
+
+/// Removes the first matching module path prefix from `name`
+///
+/// Crate disambiguators (`my_crate[1a2b3c]::`) in the name don't need to be part of the prefix.
+fn strip_prefixes<'a>(name: &'a str, prefixes: &[String]) -> &'a str {
+    prefixes
+        .iter()
+        .find_map(|prefix| strip_prefix(name, prefix.trim_end_matches("::")))
+        .unwrap_or(name)
+}
+
+fn strip_prefix<'a>(name: &'a str, prefix: &str) -> Option<&'a str> {
+    let mut rest = name;
+    for segment in prefix.split("::") {
+        rest = rest.strip_prefix(segment)?;
+        if rest.starts_with('[') && !segment.ends_with(']') {
+            rest = &rest[rest.find(']')? + 1..];
+        }
+        rest = rest.strip_prefix("::")?;
+    }
+    Some(rest)
+}
+
+/// Share of the total reached by the shown functions when using `--percentile`
+struct Pareto {
+    percentile: f64,
+    count: usize,
+    of: usize,
+    cumulative: u64,
+    total: u64,
+}
+
+/// Sorts the rows and applies `--min-stack` and `--percentile`
+///
+/// `rows` are already in order, aligning the stack sizes and collapsing closures changes the sizes though.
+fn select<'a>(args: &Args, functions: &'a mut [Row]) -> (Vec<&'a Row>, Option<Pareto>) {
+    functions.sort_by(|a, b| args.sort.compare(a, b));
+
+    let min_stack = args.min_stack.unwrap_or(0);
+
+    let mut functions: Vec<_> = functions
+        .iter()
+        .filter(|row| row.stack.unwrap_or(0) >= min_stack)
+        .filter(|row| !args.quiet_zero || row.code != 0 || row.stack.unwrap_or(0) != 0)
+        .collect();
+
+    // smallest set of functions reaching the requested share of the total
+    let mut pareto = None;
+    if let Some(percentile) = args.percentile {
+        let total: u64 = functions.iter().map(|f| args.sort.key(f)).sum();
+        let goal = total as f64 * percentile / 100.0;
+
+        let mut cumulative = 0;
+        let mut count = 0;
+        for f in &functions {
+            if cumulative as f64 >= goal {
+                break;
+            }
+            cumulative += args.sort.key(f);
+            count += 1;
+        }
+
+        let of = functions.len();
+        functions.truncate(count);
+        pareto = Some(Pareto {
+            percentile,
+            count,
+            of,
+            cumulative,
+            total,
+        });
+    }
+
+    (functions, pareto)
+}
+
+/// Applies `--strip-path-prefix` and `--relative-paths` so reports don't contain paths of the build machine
+pub fn shown_path(args: &Args, path: &Path) -> String {
+    if let Some(stripped) = args
+        .strip_path_prefix
+        .iter()
+        .find_map(|prefix| path.strip_prefix(prefix).ok())
+    {
+        return stripped.display().to_string();
+    }
+
+    if args.relative_paths {
+        let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        if let Some(relative) = std::env::current_dir()
+            .ok()
+            .and_then(|cwd| absolute.strip_prefix(cwd).ok().map(Path::to_path_buf))
+        {
+            return relative.display().to_string();
+        }
+    }
+
+    path.display().to_string()
+}
+
+/// `--stack-size` in bytes, a word takes as many bytes as an address needs hex digits
+fn stack_budget(args: &Args, address_width: usize) -> Option<u64> {
+    let size = args.stack_size?;
+    Some(match args.stack_size_unit {
+        StackUnit::Bytes => size,
+        StackUnit::Words => size * address_width as u64 / 2,
+    })
+}
+
+/// The stack size of the table, estimates are marked with `~`
+fn shown_stack(row: &Row) -> String {
+    let stack = row.stack.unwrap_or(0);
+    if row.estimated {
+        format!("~{stack}")
+    } else {
+        stack.to_string()
+    }
+}
+
+/// Percentage of the `budget` a function needing `stack` bytes takes
+fn stack_share(stack: Option<u64>, budget: u64) -> f64 {
+    stack.unwrap_or(0) as f64 * 100.0 / budget.max(1) as f64
+}
+
+/// Formats the raw `.stack_sizes` entries of the ELF at `path` for `--dump-stack-sizes`
+pub fn stack_sizes_dump(
+    args: &Args,
+    path: &Path,
+    entries: &[StackSizesEntry],
+    address_width: usize,
+) -> String {
+    let mut out = format!(
+        "`.stack_sizes` of {}:\nSection Offset {:address_width$}  Stack Function\n",
+        shown_path(args, path),
+        "Address"
+    );
+    for entry in entries {
+        let function = entry
+            .function
+            .and_then(|name| display_name(name, args.mangling))
+            .unwrap_or_else(|| String::from("(no function)"));
+        out.push_str(&format!(
+            "{:7} {:#6x} {:0address_width$x} {:6} {function}\n",
+            entry.section, entry.offset, entry.address, entry.stack
+        ));
+    }
+    let unmatched = entries.iter().filter(|e| e.function.is_none()).count();
+    out.push_str(&format!(
+        "{} entries, {unmatched} without a function\n\n",
+        entries.len()
+    ));
+    out
+}
+
+/// Sorts, filters and prints the table of functions
+pub fn print(args: &Args, mut functions: Vec<Row>, address_width: usize) {
+    let (functions, pareto) = select(args, &mut functions);
+    let budget = stack_budget(args, address_width);
+    let precision = args.percent_precision;
+    // room for the integer digits (up to 100), the decimal point and the header
+    let share_width = (3 + precision + usize::from(precision > 0)).max(4);
+
+    if args.addresses {
+        print!("{:address_width$} ", "Address");
+    }
+    if args.computed_size {
+        print!("Extent ");
+    }
+    print!("Code  Stack ");
+    if budget.is_some() {
+        print!("{:>width$} ", "Share", width = share_width + 1);
+    }
+    println!("Name");
+    for row in &functions {
+        if args.addresses {
+            print!("{:0address_width$x} ", row.address);
+        }
+        if args.computed_size {
+            match row.extent {
+                Some(extent) => print!("{:6} ", extent),
+                None => print!("{:>6} ", "-"),
+            }
+        }
+        let mut name = String::new();
+        for n in &row.names {
+            name.push_str(strip_prefixes(n, &args.strip_prefix));
+            name.push(' ');
+        }
+        if row.alias {
+            name.push_str("(alias)");
+        }
+        match row.closures {
+            0 => (),
+            1 => name.push_str("(+1 closure)"),
+            n => name.push_str(&format!("(+{n} closures)")),
+        }
+        print!("{:5} {:>5} ", row.code, shown_stack(row));
+        if let Some(budget) = budget {
+            print!(
+                "{:>share_width$.precision$}% ",
+                stack_share(row.stack, budget)
+            );
+        }
+        println!("{name}");
+    }
+
+    if let Some(Pareto {
+        percentile,
+        count,
+        of,
+        cumulative,
+        total,
+    }) = pareto
+    {
+        let cutoff = functions.last().map(|f| args.sort.key(f)).unwrap_or(0);
+        println!();
+        println!(
+            "{count} of {of} functions account for {percentile}% of the total {} size ({cumulative} of {total} bytes), cutoff at {cutoff} bytes",
+            args.sort
+        );
+    }
+}
+
+/// The report as written by `--format json` and `--format toml`
+#[derive(Serialize)]
+pub struct Document {
+    version: &'static str,
+    elf: Vec<String>,
+    notes: Vec<String>,
+    sort: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    percentile: Option<f64>,
+    // TOML needs plain values before tables, an empty array is a plain value
+    function: Vec<Entry>,
+    sections: Sections,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    baseline: Option<Sections>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    local_crates: Vec<FunctionGroup>,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    aliases: Vec<String>,
+    /// Crate and top level module of the name, empty if the name isn't a Rust path
+    #[serde(rename = "crate")]
+    krate: String,
+    module: String,
+    address: u64,
+    code_size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extent: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stack_size: Option<u64>,
+    /// The stack size is estimated from the call frame information or assumed for an intrinsic
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    estimated: bool,
+    /// Percentage of `--stack-size`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stack_share: Option<f64>,
+}
+
+impl Document {
+    /// Builds the same structure as the TOML output, keeping the metadata in front of the functions
+    pub fn to_json(&self) -> json::Value {
+        use json::Value;
+
+        let number = |n: u64| Value::Number(n.to_string());
+        let strings = |s: &[String]| Value::Array(s.iter().cloned().map(Value::String).collect());
+        let sections = |s: &Sections| {
+            Value::Object(vec![
+                ("text".to_string(), number(s.text)),
+                ("rodata".to_string(), number(s.rodata)),
+                ("data".to_string(), number(s.data)),
+                ("bss".to_string(), number(s.bss)),
+            ])
+        };
+
+        let mut members = vec![
+            (
+                "version".to_string(),
+                Value::String(self.version.to_string()),
+            ),
+            ("elf".to_string(), strings(&self.elf)),
+            ("notes".to_string(), strings(&self.notes)),
+            ("sort".to_string(), Value::String(self.sort.clone())),
+        ];
+        if let Some(percentile) = self.percentile {
+            members.push((
+                "percentile".to_string(),
+                Value::Number(percentile.to_string()),
+            ));
+        }
+        members.push(("sections".to_string(), sections(&self.sections)));
+        if let Some(baseline) = &self.baseline {
+            members.push(("baseline".to_string(), sections(baseline)));
+        }
+
+        let functions = self
+            .function
+            .iter()
+            .map(|f| {
+                let mut entry = vec![("name".to_string(), Value::String(f.name.clone()))];
+                if !f.aliases.is_empty() {
+                    entry.push(("aliases".to_string(), strings(&f.aliases)));
+                }
+                entry.push(("crate".to_string(), Value::String(f.krate.clone())));
+                entry.push(("module".to_string(), Value::String(f.module.clone())));
+                entry.push(("address".to_string(), number(f.address)));
+                entry.push(("code_size".to_string(), number(f.code_size)));
+                if let Some(extent) = f.extent {
+                    entry.push(("extent".to_string(), number(extent)));
+                }
+                if let Some(stack) = f.stack_size {
+                    entry.push(("stack_size".to_string(), number(stack)));
+                }
+                if f.estimated {
+                    entry.push(("estimated".to_string(), Value::Bool(true)));
+                }
+                if let Some(share) = f.stack_share {
+                    entry.push(("stack_share".to_string(), Value::Number(share.to_string())));
+                }
+                Value::Object(entry)
+            })
+            .collect();
+        members.push(("function".to_string(), Value::Array(functions)));
+
+        if !self.local_crates.is_empty() {
+            let groups = self
+                .local_crates
+                .iter()
+                .map(|g| {
+                    let mut group = vec![
+                        ("crate".to_string(), Value::String(g.name.clone())),
+                        ("functions".to_string(), number(g.functions as u64)),
+                        ("code".to_string(), number(g.code)),
+                        ("stack".to_string(), number(g.stack)),
+                        ("max_stack".to_string(), number(g.max_stack)),
+                        ("median_stack".to_string(), number(g.median_stack)),
+                        ("p95_stack".to_string(), number(g.p95_stack)),
+                    ];
+                    if let Some(largest) = &g.largest {
+                        group.push(("largest".to_string(), Value::String(largest.clone())));
+                    }
+                    Value::Object(group)
+                })
+                .collect();
+            members.push(("local_crates".to_string(), Value::Array(groups)));
+        }
+
+        Value::Object(members)
+    }
+}
+
+/// Collects the sorted and filtered functions along with some metadata
+pub fn document(
+    args: &Args,
+    elfs: &[Build],
+    notes: Vec<String>,
+    mut functions: Vec<Row>,
+    sections: Sections,
+    baseline: Option<Sections>,
+    address_width: usize,
+) -> Document {
+    let (functions, _) = select(args, &mut functions);
+    let budget = stack_budget(args, address_width);
+
+    let function = functions
+        .into_iter()
+        .map(|row| {
+            let mut names = row
+                .names
+                .iter()
+                .map(|n| strip_prefixes(n, &args.strip_prefix).to_string());
+            let path = row
+                .names
+                .first()
+                .map(|n| without_hashes(n))
+                .unwrap_or_default();
+            Entry {
+                name: names.next().unwrap_or_default(),
+                aliases: names.collect(),
+                krate: crates::crate_of(&path).unwrap_or_default().to_string(),
+                module: crates::module_of(&path).unwrap_or_default().to_string(),
+                address: row.address,
+                code_size: row.code,
+                extent: row.extent,
+                stack_size: row.stack,
+                estimated: row.estimated,
+                stack_share: budget.map(|budget| stack_share(row.stack, budget)),
+            }
+        })
+        .collect();
+
+    Document {
+        version: env!("CARGO_PKG_VERSION"),
+        elf: elfs.iter().map(|b| shown_path(args, &b.path)).collect(),
+        notes,
+        sort: args.sort.to_string(),
+        percentile: args.percentile,
+        sections,
+        baseline,
+        function,
+        local_crates: Vec::new(),
+    }
+}
+
+impl Document {
+    /// Serializes the document to JSON and parses it again, failing if anything got lost on the way
+    ///
+    /// Missing optional fields have to stay missing rather than turn into zero. Used by `--round-trip`.
+    pub fn check_round_trip(&self) -> anyhow::Result<()> {
+        let parsed = json::parse(&self.to_json().to_pretty_string())?;
+        let strings = |value: Option<&json::Value>| -> Vec<String> {
+            value
+                .and_then(|v| v.as_array())
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect()
+        };
+
+        if strings(parsed.get("elf")) != self.elf || strings(parsed.get("notes")) != self.notes {
+            bail!("The ELF paths or notes don't survive the round trip");
+        }
+        let sections = parsed.get("sections");
+        let section = |name: &str| sections.and_then(|s| s.get(name)).and_then(|v| v.as_u64());
+        if [
+            section("text"),
+            section("rodata"),
+            section("data"),
+            section("bss"),
+        ] != [
+            Some(self.sections.text),
+            Some(self.sections.rodata),
+            Some(self.sections.data),
+            Some(self.sections.bss),
+        ] {
+            bail!("The section sizes don't survive the round trip");
+        }
+
+        let functions = parsed
+            .get("function")
+            .and_then(|f| f.as_array())
+            .unwrap_or_default();
+        if functions.len() != self.function.len() {
+            bail!(
+                "{} of {} functions survive the round trip",
+                functions.len(),
+                self.function.len()
+            );
+        }
+        for (parsed, entry) in functions.iter().zip(&self.function) {
+            let string = |key: &str| parsed.get(key).and_then(|v| v.as_str()).unwrap_or_default();
+            let number = |key: &str| parsed.get(key).and_then(|v| v.as_u64());
+            let lost = [
+                ("name", string("name") != entry.name),
+                ("aliases", strings(parsed.get("aliases")) != entry.aliases),
+                ("crate", string("crate") != entry.krate),
+                ("module", string("module") != entry.module),
+                ("address", number("address") != Some(entry.address)),
+                ("code_size", number("code_size") != Some(entry.code_size)),
+                ("extent", number("extent") != entry.extent),
+                ("stack_size", number("stack_size") != entry.stack_size),
+                (
+                    "estimated",
+                    matches!(parsed.get("estimated"), Some(json::Value::Bool(true)))
+                        != entry.estimated,
+                ),
+            ];
+            if let Some((field, _)) = lost.iter().find(|(_, differs)| *differs) {
+                bail!(
+                    "The {field} of {} doesn't survive the round trip",
+                    entry.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the `--by-local-crate` statistics
+    pub fn with_local_crates(mut self, groups: Vec<FunctionGroup>) -> Document {
+        self.local_crates = groups;
+        self
+    }
+
+    /// One line per function, with a header line
+    pub fn to_csv(&self) -> String {
+        let mut out =
+            String::from("name,crate,module,address,code_size,extent,stack_size,estimated\n");
+        let optional = |n: Option<u64>| n.map(|n| n.to_string()).unwrap_or_default();
+        for f in &self.function {
+            out.push_str(&format!(
+                "{},{},{},{},{},{},{},{}\n",
+                csv_field(&f.name),
+                csv_field(&f.krate),
+                csv_field(&f.module),
+                f.address,
+                f.code_size,
+                optional(f.extent),
+                optional(f.stack_size),
+                f.estimated
+            ));
+        }
+        out
+    }
+}
+
+/// Columns `text` takes up in a terminal
+///
+/// Counts `char`s rather than bytes so non-ASCII names don't break the alignment, without `unicode-width`
+/// wide and combining characters still count as one column.
+pub fn display_width(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Left-aligns `text` in a column of `width` going by its `display_width`
+pub fn pad(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(display_width(text));
+    format!("{text}{:padding$}", "")
+}
+
+/// Quotes a CSV field if needed, names of generic functions contain commas
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Generates Rust source defining the stack usage of the functions, for runtime checks in firmware
+///
+/// Names are demangled without hashes, like `defmt` prints them. Functions without a stack size are left out,
+/// names several functions share without their hashes, e.g. of generic functions, get `@<address>` appended.
+pub fn rust_const(args: &Args, mut functions: Vec<Row>) -> anyhow::Result<String> {
+    let (functions, _) = select(args, &mut functions);
+
+    let mut entries = Vec::new();
+    for row in functions {
+        let Some(stack) = row.stack else {
+            continue;
+        };
+        let Ok(stack) = u32::try_from(stack) else {
+            bail!(
+                "stack size {stack} of `{}` doesn't fit the `u32` of `STACK_BUDGET`",
+                row.names.join(" ")
+            );
+        };
+        for name in &row.names {
+            let name = without_hashes(strip_prefixes(name, &args.strip_prefix));
+            entries.push((name, row.address, stack));
+        }
+    }
+    let mut count: HashMap<&str, usize> = HashMap::new();
+    for (name, ..) in &entries {
+        *count.entry(name).or_default() += 1;
+    }
+
+    let mut out = format!(
+        "// Generated by ssize {} - stack usage of each function's own frame in bytes\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    out.push_str("pub const STACK_BUDGET: &[(&str, u32)] = &[\n");
+    for (name, address, stack) in &entries {
+        if count[name.as_str()] > 1 {
+            out.push_str(&format!(
+                "    ({:?}, {stack}),\n",
+                format!("{name}@{address:#x}")
+            ));
+        } else {
+            out.push_str(&format!("    ({name:?}, {stack}),\n"));
+        }
+    }
+    out.push_str("];\n");
+    Ok(out)
+}
+
+/// Formats the functions and section totals as Prometheus text exposition format
+///
+/// Names are used without hashes to keep the series stable across builds, if that makes names collide the
+/// largest value is kept.
+pub fn prometheus(args: &Args, mut functions: Vec<Row>, sections: &Sections) -> String {
+    let (functions, _) = select(args, &mut functions);
+
+    let mut stack: BTreeMap<(String, String), u64> = BTreeMap::new();
+    let mut code: BTreeMap<(String, String), u64> = BTreeMap::new();
+    for row in functions.into_iter().filter(|row| !row.alias) {
+        let name = row.names.first().map(String::as_str).unwrap_or_default();
+        let krate = crates::crate_of(name).unwrap_or_default().to_string();
+        let key = (
+            without_hashes(strip_prefixes(name, &args.strip_prefix)),
+            krate,
+        );
+        if let Some(s) = row.stack {
+            let max = stack.entry(key.clone()).or_default();
+            *max = (*max).max(s);
+        }
+        let max = code.entry(key).or_default();
+        *max = (*max).max(row.code);
+    }
+
+    let mut out = String::new();
+    for (metric, help, values) in [
+        (
+            "ssize_function_stack_bytes",
+            "Stack usage of the function's own frame",
+            &stack,
+        ),
+        (
+            "ssize_function_code_bytes",
+            "Code size of the function",
+            &code,
+        ),
+    ] {
+        out.push_str(&format!("# HELP {metric} {help}\n# TYPE {metric} gauge\n"));
+        for ((name, krate), value) in values {
+            out.push_str(&format!(
+                "{metric}{{name=\"{}\",crate=\"{}\"}} {value}\n",
+                label_value(name),
+                label_value(krate)
+            ));
+        }
+    }
+
+    out.push_str("# HELP ssize_section_bytes Total size of the allocatable sections\n");
+    out.push_str("# TYPE ssize_section_bytes gauge\n");
+    for (section, size) in [
+        (".text", sections.text),
+        (".rodata", sections.rodata),
+        (".data", sections.data),
+        (".bss", sections.bss),
+    ] {
+        out.push_str(&format!(
+            "ssize_section_bytes{{section=\"{section}\"}} {size}\n"
+        ));
+    }
+    out
+}
+
+/// Escapes backslashes, double quotes and line feeds as the exposition format requires
+fn label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Prefixes of panic and formatting related functions, matched against names without hashes
+const FMT_PREFIXES: &[&str] = &[
+    "core::fmt::",
+    "alloc::fmt::",
+    "std::fmt::",
+    "core::panicking::",
+    "std::panicking::",
+    "core::result::unwrap_failed",
+    "core::option::expect_failed",
+    "core::option::unwrap_failed",
+    "rust_begin_unwind",
+    "__rustc::rust_begin_unwind",
+];
+
+/// Whether the function belongs to the panic/formatting machinery, including `Debug`/`Display`/... impls
+fn is_fmt(name: &str) -> bool {
+    FMT_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+        || (name.starts_with('<') && name.contains(" as core::fmt::") && name.ends_with(">::fmt"))
+}
+
+/// Sums up the functions used for formatting and panicking, printed with `--highlight-fmt`
+pub fn fmt_summary(functions: &[FunctionSize]) -> String {
+    let fmt: Vec<&FunctionSize> = functions
+        .iter()
+        .filter(|f| {
+            std::iter::once(&f.name)
+                .chain(&f.aliases)
+                .any(|n| is_fmt(n))
+        })
+        .collect();
+
+    let code: u64 = fmt.iter().map(|f| f.code).sum();
+    let stack: u64 = fmt.iter().filter_map(|f| f.stack).sum();
+    let largest = fmt
+        .iter()
+        .filter(|f| f.stack.is_some())
+        .max_by_key(|f| f.stack);
+
+    let mut out = format!(
+        "\nFormatting/panic machinery: {} functions, {code} bytes of code, {stack} bytes of stack frames in total\n",
+        fmt.len()
+    );
+    match largest {
+        Some(largest) => out.push_str(&format!(
+            "Largest frame: {} bytes in {}\n",
+            largest.stack.unwrap_or(0),
+            largest.name
+        )),
+        // a prebuilt standard library comes without `.stack_sizes`
+        None if !fmt.is_empty() => out.push_str(
+            "None of them has stack size information, build the standard library using `-Z build-std` to get it\n",
+        ),
+        None => (),
+    }
+    out
+}
+
+/// Counts the functions per stack size bucket and draws a bar for each bucket
+///
+/// `bounds` are the lower bounds of the buckets, if empty the buckets double in size from 16 bytes on.
+pub fn histogram(functions: &[FunctionSize], bounds: &[u64]) -> String {
+    let stacks: Vec<u64> = functions.iter().filter_map(|f| f.stack).collect();
+    let max = stacks.iter().copied().max().unwrap_or(0);
+
+    let mut bounds = bounds.to_vec();
+    // the first bucket always starts at zero
+    bounds.push(0);
+    if bounds.len() == 1 {
+        bounds.push(1);
+        let mut bound = 16;
+        while bound <= max {
+            bounds.push(bound);
+            bound *= 2;
+        }
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    // functions and bytes of stack per bucket
+    let mut buckets = vec![(0usize, 0u64); bounds.len()];
+    for &stack in &stacks {
+        // values below the first bound are counted in the first bucket
+        let i = bounds
+            .partition_point(|&bound| bound <= stack)
+            .saturating_sub(1);
+        buckets[i].0 += 1;
+        buckets[i].1 += stack;
+    }
+
+    let ranges: Vec<String> = bounds
+        .iter()
+        .enumerate()
+        .map(|(i, &lo)| match bounds.get(i + 1) {
+            Some(&next) if next == lo + 1 => lo.to_string(),
+            Some(&next) => format!("{lo}-{}", next - 1),
+            None => format!("{lo}+"),
+        })
+        .collect();
+    let width = ranges.iter().map(String::len).max().unwrap_or(0).max(5);
+    let most = buckets.iter().map(|b| b.0).max().unwrap_or(0).max(1);
+
+    let mut out = format!(
+        "\nStack size histogram ({} functions with stack size):\n{:>width$} Functions   Bytes Cumulative\n",
+        stacks.len(),
+        "Stack"
+    );
+    let mut cumulative = 0;
+    for (range, (count, bytes)) in ranges.iter().zip(buckets) {
+        cumulative += bytes;
+        // bars of up to 40 characters, at least one for non-empty buckets
+        let bar = (count * 40).div_ceil(most);
+        out.push_str(&format!(
+            "{range:>width$} {count:9} {bytes:7} {cumulative:10} {}\n",
+            "#".repeat(bar)
+        ));
+    }
+    out
+}
+
+/// Drops the function name `-Z function-sections` appends to the section name, e.g. `.text.unlikely.foo`
+fn section_group(name: &str) -> &str {
+    const KINDS: &[&str] = &[
+        ".text.unlikely",
+        ".text.hot",
+        ".text.startup",
+        ".text.exit",
+        ".text.split",
+        ".text",
+    ];
+    KINDS
+        .iter()
+        .find(|kind| {
+            name.strip_prefix(**kind)
+                .map(|rest| rest.is_empty() || rest.starts_with('.'))
+                .unwrap_or(false)
+        })
+        .copied()
+        .unwrap_or(name)
+}
+
+/// Code and stack of the functions in each section, for `--by-section`
+#[derive(Default)]
+pub struct BySection {
+    /// functions, code, stack and the largest frame
+    totals: BTreeMap<String, (usize, u64, u64, u64)>,
+}
+
+impl BySection {
+    /// Adds the `functions` of an ELF with the given code sections
+    pub fn add(&mut self, sections: &[(String, u64, u64)], functions: &[Row]) {
+        for f in functions.iter().filter(|f| !f.alias) {
+            let section = analysis::section_of(sections, f.address)
+                .map(section_group)
+                .unwrap_or("[other]");
+            let totals = self.totals.entry(section.to_string()).or_default();
+            totals.0 += 1;
+            totals.1 += f.code;
+            totals.2 += f.stack.unwrap_or(0);
+            totals.3 = totals.3.max(f.stack.unwrap_or(0));
+        }
+    }
+
+    pub fn format(&self) -> String {
+        let width = self
+            .totals
+            .keys()
+            .map(|section| display_width(section))
+            .chain([display_width("Section")])
+            .max()
+            .unwrap_or(0);
+        let mut out = format!(
+            "\n{} Functions   Code  Stack Largest\n",
+            pad("Section", width)
+        );
+        for (section, (functions, code, stack, largest)) in &self.totals {
+            out.push_str(&format!(
+                "{} {functions:9} {code:6} {stack:6} {largest:7}\n",
+                pad(section, width)
+            ));
+        }
+        out
+    }
+}
+
+/// Formats the `crate_groups`, to tell the stack used by a binary from the one of its library
+pub fn by_local_crate(groups: &[FunctionGroup]) -> String {
+    group_table("Local crate", groups)
+}
+
+/// Formats the `regex_groups` of `--group-by-regex`
+pub fn by_regex(groups: &[FunctionGroup]) -> String {
+    group_table("Group", groups)
+}
+
+fn group_table(header: &str, groups: &[FunctionGroup]) -> String {
+    let width = groups
+        .iter()
+        .map(|group| display_width(&group.name))
+        .chain([display_width(header)])
+        .max()
+        .unwrap_or(0);
+    let mut out = format!(
+        "\n{} Functions   Code  Stack    Max Median    P95 Largest frame\n",
+        pad(header, width)
+    );
+    for group in groups {
+        out.push_str(&format!(
+            "{} {:9} {:6} {:6} {:6} {:6} {:6} {}\n",
+            pad(&group.name, width),
+            group.functions,
+            group.code,
+            group.stack,
+            group.max_stack,
+            group.median_stack,
+            group.p95_stack,
+            group.largest.as_deref().unwrap_or("-")
+        ));
+    }
+    out
+}
+
+/// Totals and the distribution of the stack sizes of a group of functions, e.g. a local crate
+#[derive(Debug, Serialize)]
+pub struct FunctionGroup {
+    #[serde(rename = "crate")]
+    name: String,
+    functions: usize,
+    code: u64,
+    stack: u64,
+    max_stack: u64,
+    median_stack: u64,
+    p95_stack: u64,
+    /// Name of the function with the largest frame
+    #[serde(skip_serializing_if = "Option::is_none")]
+    largest: Option<String>,
+}
+
+/// Groups the functions by the workspace crate of their path, everything else ends up in `(other)`
+pub fn crate_groups(functions: &[FunctionSize], crates: &Crates) -> Vec<FunctionGroup> {
+    groups(functions, |name| {
+        match crates::crate_of(name).filter(|krate| crates.local.contains(*krate)) {
+            Some(krate) => (false, krate),
+            None => (true, "(other)"),
+        }
+    })
+}
+
+/// Groups the functions by the first capture group of `pattern` in their names, names that don't match end
+/// up in `[ungrouped]`
+pub fn regex_groups(functions: &[FunctionSize], pattern: &Regex) -> Vec<FunctionGroup> {
+    groups(functions, |name| {
+        match pattern.captures(name).and_then(|captures| captures.get(1)) {
+            Some(group) => (false, group.as_str()),
+            None => (true, "[ungrouped]"),
+        }
+    })
+}
+
+/// Groups the functions by the name `key` returns for them, sorted by name after the flag putting a group last
+///
+/// The median and 95th percentile are nearest-rank values over the functions with a stack size.
+fn groups<'a>(
+    functions: &'a [FunctionSize],
+    key: impl Fn(&'a str) -> (bool, &'a str),
+) -> Vec<FunctionGroup> {
+    #[derive(Default)]
+    struct Group<'a> {
+        functions: usize,
+        code: u64,
+        stacks: Vec<u64>,
+        largest: Option<&'a FunctionSize>,
+    }
+
+    let mut groups: BTreeMap<(bool, &str), Group> = BTreeMap::new();
+    for f in functions {
+        let group = groups.entry(key(&f.name)).or_default();
+        group.functions += 1;
+        group.code += f.code;
+        group.stacks.extend(f.stack);
+        if f.stack.is_some() && group.largest.map(|l| l.stack < f.stack).unwrap_or(true) {
+            group.largest = Some(f);
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|((_, name), mut group)| {
+            group.stacks.sort_unstable();
+            let rank = |p: f64| {
+                let index = (p * group.stacks.len() as f64).ceil() as usize;
+                group
+                    .stacks
+                    .get(index.saturating_sub(1))
+                    .copied()
+                    .unwrap_or(0)
+            };
+            FunctionGroup {
+                name: name.to_string(),
+                functions: group.functions,
+                code: group.code,
+                stack: group.stacks.iter().sum(),
+                max_stack: group.stacks.last().copied().unwrap_or(0),
+                median_stack: rank(0.5),
+                p95_stack: rank(0.95),
+                largest: group.largest.map(|f| f.name.clone()),
+            }
+        })
+        .collect()
+}
+
+/// Lists the functions using more than `ratio` times their code size as stack, likely large stack arrays
+pub fn suspicious(functions: &[FunctionSize], ratio: f64) -> String {
+    let mut suspicious: Vec<(f64, &FunctionSize)> = functions
+        .iter()
+        .filter_map(|f| {
+            let stack = f.stack.filter(|&stack| stack > 0)?;
+            let r = stack as f64 / f.code.max(1) as f64;
+            (r > ratio).then_some((r, f))
+        })
+        .collect();
+    suspicious.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut out =
+        format!("\nSuspicious functions (stack more than {ratio} times the code size):\n");
+    if suspicious.is_empty() {
+        out.push_str("none\n");
+        return out;
+    }
+    out.push_str(" Code  Stack  Ratio Name\n");
+    for (r, f) in suspicious {
+        out.push_str(&format!(
+            "{:5} {:6} {:6.1} {}\n",
+            f.code,
+            f.stack.unwrap_or(0),
+            r,
+            f.name
+        ));
+    }
+    out
+}
+
+/// Lists the functions of workspace crates that have no stack size
+///
+/// LLVM leaves functions with dynamically sized stack objects (`alloca`) out of `.stack_sizes` since their
+/// usage can't be bounded statically. Everything else in the workspace got a stack size, so a missing one most
+/// likely means dynamic allocation, otherwise naked functions or inline assembly.
+pub fn dynamic(functions: &[FunctionSize], crates: &Crates) -> String {
+    let dynamic: Vec<&FunctionSize> = functions
+        .iter()
+        .filter(|f| f.stack.is_none())
+        .filter(|f| {
+            crates::crate_of(&f.name)
+                .map(|krate| crates.local.contains(krate))
+                .unwrap_or(false)
+        })
+        .collect();
+
+    let mut out = String::from(
+        "\nPossibly dynamic stack allocation (workspace functions without stack size):\n",
+    );
+    if dynamic.is_empty() {
+        out.push_str("none\n");
+        return out;
+    }
+    out.push_str(" Code Name\n");
+    for f in dynamic {
+        out.push_str(&format!("{:5} {}\n", f.code, f.name));
+    }
+    out
+}
+
+/// Result of a stack budget check like `--fail-over`
+pub struct BudgetCheck<'a> {
+    pub name: &'a str,
+    /// `None` if the check isn't enabled
+    pub limit: Option<u64>,
+    /// Names and stack sizes of the functions exceeding the limit
+    pub over: &'a [(String, u64)],
+}
+
+/// Formats the budget checks as a JUnit test suite
+///
+/// Each function exceeding a limit becomes a failing test case, checks without any get a passing one.
+pub fn junit(checks: &[BudgetCheck]) -> String {
+    let mut cases = Vec::new();
+    for &BudgetCheck {
+        name: check,
+        limit,
+        over,
+    } in checks
+    {
+        let Some(limit) = limit else {
+            continue;
+        };
+        if over.is_empty() {
+            cases.push(format!(
+                "    <testcase classname=\"ssize.{check}\" name=\"all functions within {limit} bytes\"/>\n"
+            ));
+        }
+        for (name, stack) in over {
+            let message = format!("needs {stack} bytes of stack, the limit is {limit} bytes");
+            cases.push(format!(
+                "    <testcase classname=\"ssize.{check}\" name=\"{}\">\n      <failure message=\"{}\"/>\n    </testcase>\n",
+                xml_escape(name),
+                message
+            ));
+        }
+    }
+    if cases.is_empty() {
+        cases.push("    <testcase classname=\"ssize\" name=\"stack sizes\"/>\n".to_string());
+    }
+
+    let failures: usize = checks
+        .iter()
+        .filter(|check| check.limit.is_some())
+        .map(|check| check.over.len())
+        .sum();
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<testsuites>\n  <testsuite name=\"ssize\" tests=\"{}\" failures=\"{failures}\" errors=\"0\">\n",
+        cases.len()
+    ));
+    for case in cases {
+        out.push_str(&case);
+    }
+    out.push_str("  </testsuite>\n</testsuites>\n");
+    out
+}
+
+/// Renders a JSON document as an XML property list, for Xcode based tooling
+///
+/// Integral numbers become `<integer>`, everything else `<real>`. Property lists don't have `null`.
+pub fn plist(value: &json::Value) -> String {
+    fn write(out: &mut String, value: &json::Value, indent: usize) {
+        use json::Value;
+
+        let pad = "\t".repeat(indent);
+        match value {
+            Value::Null => (),
+            Value::Bool(b) => out.push_str(&format!("{pad}<{b}/>\n")),
+            Value::Number(n) if n.parse::<i64>().is_ok() || n.parse::<u64>().is_ok() => {
+                out.push_str(&format!("{pad}<integer>{n}</integer>\n"));
+            }
+            Value::Number(n) => out.push_str(&format!("{pad}<real>{n}</real>\n")),
+            Value::String(s) => out.push_str(&format!("{pad}<string>{}</string>\n", xml_escape(s))),
+            Value::Array(values) => {
+                out.push_str(&format!("{pad}<array>\n"));
+                for value in values {
+                    write(out, value, indent + 1);
+                }
+                out.push_str(&format!("{pad}</array>\n"));
+            }
+            Value::Object(members) => {
+                out.push_str(&format!("{pad}<dict>\n"));
+                for (key, value) in members.iter().filter(|(_, v)| !matches!(v, Value::Null)) {
+                    out.push_str(&format!("{pad}\t<key>{}</key>\n", xml_escape(key)));
+                    write(out, value, indent + 1);
+                }
+                out.push_str(&format!("{pad}</dict>\n"));
+            }
+        }
+    }
+
+    let mut out = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n",
+        "<plist version=\"1.0\">\n"
+    ));
+    write(&mut out, value, 0);
+    out.push_str("</plist>\n");
+    out
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Numbers of symbols and sections as found in the ELFs, the output of `--count-only`
+#[derive(Serialize, Default)]
+pub struct Counts {
+    defined: usize,
+    undefined: usize,
+    with_stack: usize,
+    allocatable_sections: usize,
+}
+
+impl Counts {
+    /// Adds the functions and sections of one more ELF
+    pub fn add(&mut self, functions: &Functions, allocatable_sections: usize) {
+        self.defined += functions.defined.len();
+        self.undefined += functions.undefined.len();
+        self.with_stack += functions
+            .defined
+            .values()
+            .filter(|f| f.stack().is_some())
+            .count();
+        self.allocatable_sections += allocatable_sections;
+    }
+
+    pub fn print(&self) {
+        println!("Defined functions: {}", self.defined);
+        println!("Undefined symbols: {}", self.undefined);
+        println!("Functions with stack size: {}", self.with_stack);
+        println!("Allocatable sections: {}", self.allocatable_sections);
+    }
+
+    pub fn to_json(&self) -> json::Value {
+        use json::Value;
+
+        let number = |n: usize| Value::Number(n.to_string());
+        Value::Object(vec![
+            ("defined".to_string(), number(self.defined)),
+            ("undefined".to_string(), number(self.undefined)),
+            ("with_stack".to_string(), number(self.with_stack)),
+            (
+                "allocatable_sections".to_string(),
+                number(self.allocatable_sections),
+            ),
+        ])
+    }
+}
+
+/// Aggregates of the shown functions, the output of `--summary-only`
+#[derive(Serialize)]
+pub struct Summary {
+    functions: usize,
+    total_code: u64,
+    total_stack: u64,
+    max_stack: u64,
+    mean_stack: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fail_over: Option<u64>,
+    /// Number of functions exceeding `fail_over`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    over_budget: Option<usize>,
+}
+
+impl Summary {
+    pub fn of(args: &Args, mut functions: Vec<Row>, over_budget: usize) -> Summary {
+        let (functions, _) = select(args, &mut functions);
+        let functions: Vec<&Row> = functions.into_iter().filter(|row| !row.alias).collect();
+
+        let total_stack: u64 = functions.iter().filter_map(|f| f.stack).sum();
+        let with_stack = functions.iter().filter(|f| f.stack.is_some()).count();
+        Summary {
+            functions: functions.len(),
+            total_code: functions.iter().map(|f| f.code).sum(),
+            total_stack,
+            max_stack: functions.iter().filter_map(|f| f.stack).max().unwrap_or(0),
+            mean_stack: if with_stack > 0 {
+                total_stack as f64 / with_stack as f64
+            } else {
+                0.0
+            },
+            fail_over: args.fail_over,
+            over_budget: args.fail_over.map(|_| over_budget),
+        }
+    }
+
+    pub fn print(&self) {
+        println!(
+            "{} functions, {} bytes of code, stack: {} bytes total, {} bytes max, {:.1} bytes mean",
+            self.functions, self.total_code, self.total_stack, self.max_stack, self.mean_stack
+        );
+        match (self.fail_over, self.over_budget) {
+            (Some(limit), Some(0)) => {
+                println!("Budget: pass, no function needs more than {limit} bytes")
+            }
+            (Some(limit), Some(over)) => {
+                println!("Budget: FAIL, {over} functions need more than {limit} bytes")
+            }
+            _ => (),
+        }
+    }
+
+    pub fn to_json(&self) -> json::Value {
+        use json::Value;
+
+        let number = |n: u64| Value::Number(n.to_string());
+        let mut members = vec![
+            ("functions".to_string(), number(self.functions as u64)),
+            ("total_code".to_string(), number(self.total_code)),
+            ("total_stack".to_string(), number(self.total_stack)),
+            ("max_stack".to_string(), number(self.max_stack)),
+            (
+                "mean_stack".to_string(),
+                Value::Number(format!("{:.1}", self.mean_stack)),
+            ),
+        ];
+        if let (Some(limit), Some(over)) = (self.fail_over, self.over_budget) {
+            members.push(("fail_over".to_string(), number(limit)));
+            members.push(("over_budget".to_string(), number(over as u64)));
+        }
+        Value::Object(members)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::Function;
+
+    fn group(name: &str) -> FunctionGroup {
+        FunctionGroup {
+            name: name.to_string(),
+            functions: 1,
+            code: 2,
+            stack: 3,
+            max_stack: 3,
+            median_stack: 3,
+            p95_stack: 3,
+            largest: None,
+        }
+    }
+
+    /// Symbols of the same functions in two builds with different `-C metadata`
+    const LEGACY: [(&str, &str); 2] = [
+        (
+            "_ZN7mangled1S6method17h66ddc5acc62be27bE",
+            "_ZN7mangled1S6method17h2dfe9106a1090e74E",
+        ),
+        (
+            "_ZN7mangled3run17heea2ebd6233feed0E",
+            "_ZN7mangled3run17hcc5203fbdce9e80cE",
+        ),
+    ];
+    const V0: [(&str, &str); 2] = [
+        (
+            "_RNvMCs2lF5s0zIYfu_7mangledNtB2_1S6method",
+            "_RNvMCs4JNDgo5hoFl_7mangledNtB2_1S6method",
+        ),
+        (
+            "_RINvCs2lF5s0zIYfu_7mangled3runmEB2_",
+            "_RINvCs4JNDgo5hoFl_7mangled3runmEB2_",
+        ),
+    ];
+
+    fn demangled(symbol: &str) -> String {
+        rustc_demangle::demangle(symbol).to_string()
+    }
+
+    #[test]
+    fn removes_legacy_hashes() {
+        assert_eq!(
+            without_hashes(&demangled(LEGACY[0].0)),
+            "mangled::S::method"
+        );
+        for (a, b) in LEGACY {
+            assert_ne!(demangled(a), demangled(b));
+            assert_eq!(without_hashes(&demangled(a)), without_hashes(&demangled(b)));
+            assert_eq!(comparable(&demangled(a)), comparable(&demangled(b)));
+        }
+    }
+
+    #[test]
+    fn removes_v0_disambiguators() {
+        assert_eq!(without_hashes(&demangled(V0[0].0)), "<mangled::S>::method");
+        assert_eq!(without_hashes(&demangled(V0[1].0)), "mangled::run::<u32>");
+        for (a, b) in V0 {
+            assert_ne!(demangled(a), demangled(b));
+            assert_eq!(without_hashes(&demangled(a)), without_hashes(&demangled(b)));
+            assert_eq!(comparable(&demangled(a)), comparable(&demangled(b)));
+        }
+    }
+
+    #[test]
+    fn v0_and_legacy_names_are_comparable() {
+        assert_eq!(
+            comparable(&demangled(V0[0].0)),
+            comparable(&demangled(LEGACY[0].0))
+        );
+        // generic arguments are only part of v0 names
+        assert_eq!(comparable(&demangled(V0[1].0)), "mangled::run<u32>");
+        assert_eq!(comparable(&demangled(LEGACY[1].0)), "mangled::run");
+    }
+
+    fn row(address: u64, name: &str, stack: u64, alias: bool) -> Row {
+        Row {
+            address,
+            names: vec![name.to_string()],
+            code: 4,
+            extent: None,
+            stack: Some(stack),
+            estimated: false,
+            alias,
+            closures: 0,
+        }
+    }
+
+    #[test]
+    fn closures_are_added_to_their_parent() {
+        let rows = vec![
+            row(0x10, "app::main", 32, false),
+            row(0x20, "app::main::{closure#0}", 16, false),
+            row(0x30, "app::run::{closure#0}", 8, false),
+            row(0x40, "app::main::{closure#1}", 8, false),
+            row(0x50, "app::run::{closure#1}", 8, false),
+        ];
+        let collapsed = collapse_closures(rows);
+        let summary: Vec<_> = collapsed
+            .iter()
+            .map(|row| (row.names[0].as_str(), row.code, row.stack, row.closures))
+            .collect();
+        // parents without a row of their own get one after the other functions
+        assert_eq!(
+            summary,
+            [("app::main", 12, Some(56), 2), ("app::run", 8, Some(16), 2)]
+        );
+    }
+
+    #[test]
+    fn select_breaks_ties_by_address() {
+        use clap::Parser;
+
+        let args = Args::parse_from(["ssize"]);
+        let mut rows = vec![
+            row(0x30, "c", 16, false),
+            row(0x20, "b", 16, false),
+            row(0x10, "z", 16, false),
+            row(0x10, "a", 16, true),
+            row(0x40, "d", 32, false),
+        ];
+        let (selected, _) = select(&args, &mut rows);
+        let order: Vec<_> = selected.iter().map(|row| row.names[0].as_str()).collect();
+        // the alias stays behind its function although its name sorts first
+        assert_eq!(order, ["d", "z", "a", "b", "c"]);
+    }
+
+    #[test]
+    fn json_round_trips() {
+        use clap::Parser;
+        use xmas_elf::ElfFile;
+
+        let args = Args::parse_from(["ssize", "--percentile", "99.5"]);
+        let elf = include_bytes!("../fixtures/functions-x86_64.o").to_vec();
+        let functions = analysis::analyze_executable(&elf, false).unwrap();
+        let sections = Sections::of(&ElfFile::new(&elf).unwrap());
+        let build = Build {
+            path: "fixtures/functions-x86_64.o".into(),
+            elf: elf.clone().into(),
+            notes: Vec::new(),
+        };
+        let notes = vec!["a \"quoted\" note\twith ü and \\".to_string()];
+        let document = document(
+            &args,
+            &[build],
+            notes,
+            rows(&args, &functions),
+            sections,
+            Some(Sections::default()),
+            16,
+        );
+
+        let json = document.to_json();
+        let function = json.get("function").and_then(|f| f.as_array());
+        assert!(function.is_some_and(|f| !f.is_empty()));
+        assert_eq!(json::parse(&json.to_pretty_string()).unwrap(), json);
+        assert_eq!(json::parse(&json.to_compact_string()).unwrap(), json);
+        document.check_round_trip().unwrap();
+    }
+
+    #[test]
+    fn estimates_are_marked() {
+        use clap::Parser;
+
+        let args = Args::parse_from(["ssize", "--from-cfi"]);
+        let mut estimated = row(0x20, "app::estimated", 48, false);
+        estimated.estimated = true;
+        let rows = vec![row(0x10, "app::exact", 32, false), estimated];
+        assert_eq!(shown_stack(&rows[0]), "32");
+        assert_eq!(shown_stack(&rows[1]), "~48");
+
+        let build = Build {
+            path: "app".into(),
+            elf: Vec::new().into(),
+            notes: Vec::new(),
+        };
+        let document = document(
+            &args,
+            &[build],
+            Vec::new(),
+            rows,
+            Sections::default(),
+            None,
+            16,
+        );
+        let csv = document.to_csv();
+        assert!(csv.contains("\napp::estimated,app,,32,4,,48,true\n"));
+        assert!(csv.contains("\napp::exact,app,,16,4,,32,false\n"));
+
+        let json = document.to_json();
+        let functions = json.get("function").and_then(|f| f.as_array()).unwrap();
+        let estimates: Vec<_> = functions
+            .iter()
+            .map(|f| f.get("estimated").is_some())
+            .collect();
+        assert_eq!(estimates, [true, false]);
+        document.check_round_trip().unwrap();
+    }
+
+    #[test]
+    fn display_names_fall_back_to_the_symbol() {
+        for mangling in [Mangling::Auto, Mangling::Legacy, Mangling::V0] {
+            assert_eq!(display_name("", mangling), None);
+            assert_eq!(display_name(" \t", mangling), None);
+            // not valid legacy or v0 mangling, shown as it is
+            assert_eq!(
+                display_name("_ZN3foo", mangling).as_deref(),
+                Some("_ZN3foo")
+            );
+            assert_eq!(display_name("_RNvC", mangling).as_deref(), Some("_RNvC"));
+            assert_eq!(display_name("memcpy", mangling).as_deref(), Some("memcpy"));
+        }
+    }
+
+    #[test]
+    fn rows_fall_back_to_the_address() {
+        use clap::Parser;
+
+        let args = Args::parse_from(["ssize"]);
+        let defined = [
+            (0x100, vec![""]),
+            (0x200, vec!["  "]),
+            (0x300, vec!["_ZN3foo"]),
+            (
+                0x400,
+                vec!["", "_ZN4core3ptr13drop_in_place17h0123456789abcdefE"],
+            ),
+        ]
+        .into_iter()
+        .map(|(address, names)| (address, Function::new(names, 4, Some(8))))
+        .collect();
+        let functions = Functions {
+            have_32_bit_addresses: false,
+            undefined: Default::default(),
+            defined,
+            matched: 4,
+            unmatched: Vec::new(),
+            entries: Vec::new(),
+            malformed: Vec::new(),
+        };
+
+        let mut rows = rows(&args, &functions);
+        rows.sort_by_key(|row| row.address);
+        let names: Vec<_> = rows.iter().map(|row| row.names.clone()).collect();
+        assert_eq!(
+            names,
+            [
+                vec!["0x100"],
+                vec!["0x200"],
+                vec!["_ZN3foo"],
+                vec!["core::ptr::drop_in_place::h0123456789abcdef"],
+            ]
+        );
+    }
+
+    #[test]
+    fn rust_const_disambiguates_names() {
+        use clap::Parser;
+
+        let args = Args::parse_from(["ssize"]);
+        let mut rows = vec![
+            row(0x10, "app::run::h0123456789abcdef", 32, false),
+            row(0x20, "app::run::hfedcba9876543210", 16, false),
+            row(0x30, "app::main", 8, false),
+            row(0x40, "app::unknown", 0, false),
+        ];
+        rows[3].stack = None;
+        let generated = rust_const(&args, rows).unwrap();
+        let entries: Vec<_> = generated
+            .lines()
+            .filter(|l| l.starts_with("    ("))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                "    (\"app::run@0x10\", 32),",
+                "    (\"app::run@0x20\", 16),",
+                "    (\"app::main\", 8),"
+            ]
+        );
+    }
+
+    #[test]
+    fn pads_by_chars() {
+        assert_eq!(pad("größe", 7), "größe  ");
+        assert_eq!(pad("size", 2), "size");
+    }
+
+    #[test]
+    fn aligns_non_ascii_groups() {
+        let table = group_table("Group", &[group("größe"), group("sizes")]);
+        let lines: Vec<_> = table.lines().filter(|line| !line.is_empty()).collect();
+        // the name column is as wide as the longest name in chars, not in bytes
+        assert!(lines[0].starts_with("Group Functions "));
+        assert!(lines[1].starts_with("größe         1 "));
+        assert!(lines[2].starts_with("sizes         1 "));
+    }
+}
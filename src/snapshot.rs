@@ -0,0 +1,419 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::AddAssign,
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use xmas_elf::{
+    sections::{ShType, SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE},
+    ElfFile,
+};
+
+use crate::report::{self, Row};
+
+/// Data saved via `--output` and compared against via `--baseline`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub sections: Option<Sections>,
+    #[serde(default, rename = "function", skip_serializing_if = "Vec::is_empty")]
+    pub functions: Vec<FunctionSize>,
+}
+
+/// Sizes of a function, the names are stored without hashes to match them across builds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionSize {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    pub code: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stack: Option<u64>,
+    /// The stack size is estimated from the call frame information (`--from-cfi`) or assumed by
+    /// `--intrinsic-stacks`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub estimated: bool,
+}
+
+impl FunctionSize {
+    pub fn of(row: &Row) -> FunctionSize {
+        let mut names = row.names.iter().map(|n| report::without_hashes(n));
+        FunctionSize {
+            name: names.next().unwrap_or_default(),
+            aliases: names.collect(),
+            code: row.code,
+            stack: row.stack,
+            estimated: row.estimated,
+        }
+    }
+}
+
+/// Totals of the allocatable sections, grouped like `size` does
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct Sections {
+    pub text: u64,
+    pub rodata: u64,
+    pub data: u64,
+    pub bss: u64,
+}
+
+impl Sections {
+    pub fn of(elf: &ElfFile) -> Sections {
+        let mut sections = Sections::default();
+
+        for section in elf.section_iter() {
+            let flags = section.flags();
+            if flags & SHF_ALLOC == 0 {
+                continue;
+            }
+
+            let size = section.size();
+            if flags & SHF_EXECINSTR != 0 {
+                sections.text += size;
+            } else if flags & SHF_WRITE == 0 {
+                sections.rodata += size;
+            } else if section.get_type() == Ok(ShType::NoBits) {
+                sections.bss += size;
+            } else {
+                sections.data += size;
+            }
+        }
+
+        sections
+    }
+
+    /// Bytes occupying non-volatile memory (`.data` needs its initializers stored there)
+    pub fn flash(&self) -> u64 {
+        self.text + self.rodata + self.data
+    }
+
+    /// Bytes occupying RAM at runtime
+    pub fn ram(&self) -> u64 {
+        self.data + self.bss
+    }
+}
+
+impl AddAssign for Sections {
+    fn add_assign(&mut self, other: Sections) {
+        self.text += other.text;
+        self.rodata += other.rodata;
+        self.data += other.data;
+        self.bss += other.bss;
+    }
+}
+
+/// Start of a snapshot written by `--format binary`, followed by the format version
+const BINARY_MAGIC: &[u8; 4] = b"SSZB";
+const BINARY_VERSION: u8 = 2;
+
+impl Snapshot {
+    /// Reads a TOML snapshot or one written by `--format binary`
+    pub fn load(path: &Path) -> anyhow::Result<Snapshot> {
+        let content = std::fs::read(path)?;
+        if content.starts_with(BINARY_MAGIC) {
+            return Snapshot::from_binary(&content)
+                .with_context(|| format!("{} is a corrupt binary snapshot", path.display()));
+        }
+        Ok(toml::from_str(std::str::from_utf8(&content)?)?)
+    }
+
+    /// Encodes the snapshot compactly for `--format binary`
+    ///
+    /// After the magic `SSZB` and the version byte (2) all numbers are unsigned LEB128 and strings are their
+    /// length followed by UTF-8. A flag byte tells whether the section sizes `.text`, `.rodata`, `.data` and
+    /// `.bss` follow, then the number of functions and for each its name, the number of aliases, the aliases,
+    /// the code size, the stack size plus one, zero meaning unknown, and a byte that is 1 for estimated stack
+    /// sizes. Version 1 lacks that byte.
+    pub fn to_binary(&self) -> Vec<u8> {
+        fn number(out: &mut Vec<u8>, n: u64) {
+            leb128::write::unsigned(out, n).expect("writing to a Vec doesn't fail");
+        }
+        fn string(out: &mut Vec<u8>, s: &str) {
+            number(out, s.len() as u64);
+            out.extend_from_slice(s.as_bytes());
+        }
+
+        let mut out = BINARY_MAGIC.to_vec();
+        out.push(BINARY_VERSION);
+        match &self.sections {
+            Some(sections) => {
+                out.push(1);
+                for n in [sections.text, sections.rodata, sections.data, sections.bss] {
+                    number(&mut out, n);
+                }
+            }
+            None => out.push(0),
+        }
+        number(&mut out, self.functions.len() as u64);
+        for f in &self.functions {
+            string(&mut out, &f.name);
+            number(&mut out, f.aliases.len() as u64);
+            for alias in &f.aliases {
+                string(&mut out, alias);
+            }
+            number(&mut out, f.code);
+            number(&mut out, f.stack.map(|stack| stack + 1).unwrap_or(0));
+            out.push(u8::from(f.estimated));
+        }
+        out
+    }
+
+    fn from_binary(data: &[u8]) -> anyhow::Result<Snapshot> {
+        fn number(cursor: &mut &[u8]) -> anyhow::Result<u64> {
+            Ok(leb128::read::unsigned(cursor)?)
+        }
+        fn string(cursor: &mut &[u8]) -> anyhow::Result<String> {
+            let len = number(cursor)? as usize;
+            if len > cursor.len() {
+                bail!("a string reaches past the end");
+            }
+            let (s, rest) = cursor.split_at(len);
+            *cursor = rest;
+            Ok(String::from_utf8(s.to_vec())?)
+        }
+        fn byte(cursor: &mut &[u8]) -> anyhow::Result<u8> {
+            let (&b, rest) = cursor.split_first().context("it ends early")?;
+            *cursor = rest;
+            Ok(b)
+        }
+
+        let mut cursor = &data[BINARY_MAGIC.len()..];
+        let version = byte(&mut cursor)?;
+        if !(1..=BINARY_VERSION).contains(&version) {
+            bail!("format version {version} isn't supported, only up to {BINARY_VERSION}");
+        }
+        let sections = match byte(&mut cursor)? {
+            0 => None,
+            _ => Some(Sections {
+                text: number(&mut cursor)?,
+                rodata: number(&mut cursor)?,
+                data: number(&mut cursor)?,
+                bss: number(&mut cursor)?,
+            }),
+        };
+        let count = number(&mut cursor)?;
+        let mut functions = Vec::new();
+        for _ in 0..count {
+            let name = string(&mut cursor)?;
+            let aliases = (0..number(&mut cursor)?)
+                .map(|_| string(&mut cursor))
+                .collect::<anyhow::Result<_>>()?;
+            functions.push(FunctionSize {
+                name,
+                aliases,
+                code: number(&mut cursor)?,
+                stack: number(&mut cursor)?.checked_sub(1),
+                estimated: version > 1 && byte(&mut cursor)? != 0,
+            });
+        }
+        Ok(Snapshot {
+            sections,
+            functions,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Names and aliases of all functions, see [`report::comparable`]
+    pub fn function_names(&self) -> HashSet<String> {
+        self.functions
+            .iter()
+            .flat_map(|f| std::iter::once(&f.name).chain(&f.aliases))
+            .map(|name| report::comparable(name))
+            .collect()
+    }
+}
+
+/// Prints the section totals next to the ones in the baseline
+pub fn print_section_diff(current: &Sections, baseline: &Sections) {
+    let rows = [
+        (".text", current.text, baseline.text),
+        (".rodata", current.rodata, baseline.rodata),
+        (".data", current.data, baseline.data),
+        (".bss", current.bss, baseline.bss),
+    ];
+
+    println!();
+    println!("Section       Size  Baseline    Delta");
+    for (name, size, base) in rows {
+        println!("{:8} {:9} {:9} {:>8}", name, size, base, delta(size, base));
+    }
+    println!(
+        "Flash: {} bytes, RAM: {} bytes",
+        delta(current.flash(), baseline.flash()),
+        delta(current.ram(), baseline.ram())
+    );
+}
+
+fn by_name(functions: &[FunctionSize]) -> HashMap<String, &FunctionSize> {
+    functions
+        .iter()
+        .map(|f| (report::comparable(&f.name), f))
+        .collect()
+}
+
+/// Returns the name, baseline and current stack size of the functions of the baseline which need at least
+/// `threshold` bytes more stack now, largest growth first
+///
+/// Functions are matched like in [`print_function_diff`], new ones are left to `--fail-on-new`.
+pub fn regressions<'a>(
+    current: &'a [FunctionSize],
+    baseline: &[FunctionSize],
+    threshold: u64,
+) -> Vec<(&'a str, u64, u64)> {
+    let baseline_by_name = by_name(baseline);
+    let mut regressions: Vec<_> = by_name(current)
+        .into_iter()
+        .filter_map(|(name, f)| {
+            let base = baseline_by_name.get(&name)?.stack.unwrap_or(0);
+            let stack = f.stack.unwrap_or(0);
+            (stack > base && stack - base >= threshold).then_some((f.name.as_str(), base, stack))
+        })
+        .collect();
+    regressions.sort_by(|a, b| (b.2 - b.1).cmp(&(a.2 - a.1)).then(a.0.cmp(b.0)));
+    regressions
+}
+
+/// Prints the functions whose stack usage differs from the baseline, including new and removed ones
+///
+/// Functions are matched by their [`report::comparable`] name, the largest changes come first. Changes of less
+/// than `threshold` bytes are left out, new and removed functions are always shown.
+pub fn print_function_diff(current: &[FunctionSize], baseline: &[FunctionSize], threshold: u64) {
+    let current_by_name = by_name(current);
+    let baseline_by_name = by_name(baseline);
+
+    // (baseline stack, current stack, name, marker)
+    let mut changes: Vec<(Option<u64>, Option<u64>, &str, &str)> = Vec::new();
+    let (mut added, mut removed, mut hidden) = (0, 0, 0);
+    for (name, f) in &current_by_name {
+        match baseline_by_name.get(name) {
+            Some(base) if base.stack != f.stack => {
+                if f.stack.unwrap_or(0).abs_diff(base.stack.unwrap_or(0)) < threshold {
+                    hidden += 1;
+                } else {
+                    changes.push((base.stack, f.stack, &f.name, ""));
+                }
+            }
+            Some(_) => (),
+            None => {
+                added += 1;
+                changes.push((None, f.stack, &f.name, " (new)"));
+            }
+        }
+    }
+    for (name, base) in &baseline_by_name {
+        if !current_by_name.contains_key(name) {
+            removed += 1;
+            changes.push((base.stack, None, &base.name, " (removed)"));
+        }
+    }
+    let change = |(base, current, ..): &(Option<u64>, Option<u64>, &str, &str)| {
+        current.unwrap_or(0).abs_diff(base.unwrap_or(0))
+    };
+    changes.sort_by(|a, b| change(b).cmp(&change(a)).then(a.2.cmp(b.2)));
+
+    let stack = |s: Option<u64>| s.map(|s| s.to_string()).unwrap_or_else(|| "-".to_string());
+    println!();
+    println!("Baseline  Stack    Delta Name");
+    for (base, current, name, marker) in &changes {
+        println!(
+            "{:>8} {:>6} {:>8} {name}{marker}",
+            stack(*base),
+            stack(*current),
+            delta(current.unwrap_or(0), base.unwrap_or(0))
+        );
+    }
+
+    let total = |functions: &[FunctionSize]| -> (u64, u64) {
+        (
+            functions.iter().filter_map(|f| f.stack).sum(),
+            functions.iter().map(|f| f.code).sum(),
+        )
+    };
+    let (stack_now, code_now) = total(current);
+    let (stack_base, code_base) = total(baseline);
+    println!(
+        "{} changed, {added} new, {removed} removed functions. Stack: {} bytes, code: {} bytes",
+        changes.len() - added - removed,
+        delta(stack_now, stack_base),
+        delta(code_now, code_base)
+    );
+    if hidden > 0 {
+        println!("{hidden} functions changed by less than {threshold} bytes");
+    }
+}
+
+fn delta(current: u64, baseline: u64) -> String {
+    format!("{:+}", current as i64 - baseline as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn function(name: &str, stack: Option<u64>) -> FunctionSize {
+        FunctionSize {
+            name: name.to_string(),
+            aliases: Vec::new(),
+            code: 4,
+            stack,
+            estimated: false,
+        }
+    }
+
+    #[test]
+    fn regressions_respect_the_threshold() {
+        let baseline = [
+            function("app::grew", Some(100)),
+            function("app::jitter", Some(100)),
+            function("app::shrunk", Some(100)),
+            function("app::gained_a_frame", None),
+        ];
+        let current = [
+            function("app::grew", Some(200)),
+            function("app::jitter", Some(104)),
+            function("app::shrunk", Some(10)),
+            function("app::gained_a_frame", Some(16)),
+            function("app::new", Some(1000)),
+        ];
+        assert_eq!(
+            regressions(&current, &baseline, 8),
+            [("app::grew", 100, 200), ("app::gained_a_frame", 0, 16)]
+        );
+        assert_eq!(regressions(&current, &baseline, 0).len(), 3);
+    }
+
+    #[test]
+    fn binary_snapshots_keep_the_estimates() {
+        let mut estimated = function("app::estimated", Some(48));
+        estimated.estimated = true;
+        let snapshot = Snapshot {
+            sections: None,
+            functions: vec![function("app::exact", Some(32)), estimated],
+        };
+        let read = Snapshot::from_binary(&snapshot.to_binary()).unwrap();
+        let flags: Vec<_> = read
+            .functions
+            .iter()
+            .map(|f| (f.name.as_str(), f.stack, f.estimated))
+            .collect();
+        assert_eq!(
+            flags,
+            [
+                ("app::exact", Some(32), false),
+                ("app::estimated", Some(48), true)
+            ]
+        );
+
+        // version 1 has no estimates
+        let mut old = b"SSZB\x01\x00\x01".to_vec();
+        old.extend([8, b'a', b'p', b'p', b':', b':', b'o', b'l', b'd', 0, 4, 17]);
+        let read = Snapshot::from_binary(&old).unwrap();
+        assert_eq!(read.functions[0].stack, Some(16));
+        assert!(!read.functions[0].estimated);
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use cargo_project::Project;
+use log::debug;
+
+use crate::{
+    cargo::{self, Build},
+    compare, json, report, Args,
+};
+
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// Rebuilds and reports whenever a file in the package changes
+///
+/// A failed build keeps the last good report on screen below the compiler errors, a failed analysis shows the
+/// error until the next change.
+pub fn run(args: &Args) -> anyhow::Result<()> {
+    let project = Project::query(std::env::current_dir()?)?;
+    let root = project.toml().parent().unwrap().to_path_buf();
+    // the files written on every build would trigger the next one
+    let written = [
+        Some(project.target_dir().to_path_buf()),
+        args.watch_history.clone(),
+        args.output.clone(),
+        args.output_dir.clone(),
+    ];
+    let ignored: Vec<PathBuf> = written
+        .into_iter()
+        .flatten()
+        .filter_map(|path| std::path::absolute(path).ok())
+        .collect();
+
+    let mut last_good = None;
+    let mut last_change = None;
+    let mut max_stacks = Vec::new();
+    loop {
+        let change = newest_change(&root, &ignored);
+        if last_change != Some(change) {
+            last_change = Some(change);
+            debug!("Change detected, rebuilding");
+
+            clear_screen()?;
+            match cargo::build_artifact(args) {
+                Ok(build) => {
+                    clear_screen()?;
+                    match report(args, &build) {
+                        Ok(max_stack) => {
+                            max_stacks.extend(max_stack);
+                            last_good = Some(build);
+                        }
+                        Err(err) => banner("ANALYSIS FAILED", &err, false),
+                    }
+                }
+                Err(err) => {
+                    banner("BUILD FAILED", &err, last_good.is_some());
+                    if let Some(build) = &last_good {
+                        if let Err(err) = crate::report_all(args, std::slice::from_ref(build)) {
+                            println!("{err:#}");
+                        }
+                    }
+                }
+            }
+
+            println!();
+            if let Some(&max_stack) = max_stacks.last() {
+                println!(
+                    "Max stack over {} builds: {} {max_stack} bytes",
+                    max_stacks.len(),
+                    sparkline(&max_stacks)
+                );
+            }
+            println!(
+                "Watching {} for changes, press Ctrl-C to stop",
+                root.display()
+            );
+        }
+
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+/// Prints the report of the build and appends it to the `--watch-history`, returning its maximum stack then
+fn report(args: &Args, build: &Build) -> anyhow::Result<Option<u64>> {
+    crate::report_all(args, std::slice::from_ref(build))?;
+    let Some(path) = &args.watch_history else {
+        return Ok(None);
+    };
+    let entry = HistoryEntry::of(args, build)?;
+    entry.append(path)?;
+    Ok(Some(entry.max_stack))
+}
+
+/// Shows why there's no new report, watching goes on with the next change
+fn banner(title: &str, err: &anyhow::Error, previous: bool) {
+    println!();
+    println!("==================================================");
+    println!("{title}: {err:#}");
+    if previous {
+        println!("Showing previous results");
+    }
+    println!("==================================================");
+}
+
+/// Summary of a build appended to the `--watch-history` file
+struct HistoryEntry {
+    /// Seconds since the Unix epoch
+    timestamp: u64,
+    total_stack: u64,
+    max_stack: u64,
+    /// The function with the largest frame
+    top: Option<String>,
+}
+
+impl HistoryEntry {
+    fn of(args: &Args, build: &Build) -> anyhow::Result<HistoryEntry> {
+        let functions = compare::function_sizes(args, build)?;
+        let top = functions
+            .iter()
+            .filter(|f| f.stack.is_some())
+            .max_by_key(|f| f.stack);
+        Ok(HistoryEntry {
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_secs(),
+            total_stack: functions.iter().filter_map(|f| f.stack).sum(),
+            max_stack: top.and_then(|f| f.stack).unwrap_or(0),
+            top: top.map(|f| f.name.clone()),
+        })
+    }
+
+    /// Appends a line to the history, CSV for `.csv` files and NDJSON otherwise
+    fn append(&self, path: &Path) -> anyhow::Result<()> {
+        let csv = path.extension().map(|e| e == "csv").unwrap_or(false);
+        let new = std::fs::metadata(path)
+            .map(|m| m.len() == 0)
+            .unwrap_or(true);
+        let top = self.top.as_deref().unwrap_or_default();
+
+        let mut line = String::new();
+        if csv {
+            if new {
+                line.push_str("timestamp,total_stack,max_stack,top\n");
+            }
+            line.push_str(&format!(
+                "{},{},{},{}\n",
+                self.timestamp,
+                self.total_stack,
+                self.max_stack,
+                report::csv_field(top)
+            ));
+        } else {
+            let number = |n: u64| json::Value::Number(n.to_string());
+            let value = json::Value::Object(vec![
+                ("timestamp".to_string(), number(self.timestamp)),
+                ("total_stack".to_string(), number(self.total_stack)),
+                ("max_stack".to_string(), number(self.max_stack)),
+                ("top".to_string(), json::Value::String(top.to_string())),
+            ]);
+            line = value.to_compact_string() + "\n";
+        }
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .with_context(|| format!("Failed to append to {}", path.display()))
+    }
+}
+
+/// Draws the values as a line of block characters scaled between their minimum and maximum
+fn sparkline(values: &[u64]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let min = values.iter().copied().min().unwrap_or(0);
+    let max = values.iter().copied().max().unwrap_or(0);
+    values
+        .iter()
+        .map(|&v| {
+            let level = if max > min {
+                ((v - min) * 7 / (max - min)) as usize
+            } else {
+                0
+            };
+            BARS[level]
+        })
+        .collect()
+}
+
+fn clear_screen() -> anyhow::Result<()> {
+    // flush right away, otherwise the escape sequence might only be written after cargo's output
+    let mut stdout = std::io::stdout();
+    write!(stdout, "{CLEAR_SCREEN}")?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Latest modification time of any file in the package, ignoring the `ignored` files and directories like the
+/// target directory
+fn newest_change(root: &Path, ignored: &[PathBuf]) -> SystemTime {
+    let mut newest = SystemTime::UNIX_EPOCH;
+    let mut dirs: Vec<PathBuf> = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let hidden = entry.file_name().to_string_lossy().starts_with('.')
+                && entry.file_name() != ".cargo";
+            if hidden || ignored.contains(&path) {
+                continue;
+            }
+
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                dirs.push(path);
+            } else if let Ok(modified) = metadata.modified() {
+                newest = newest.max(modified);
+            }
+        }
+    }
+    newest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_files_are_no_changes() {
+        let root = std::env::temp_dir().join(format!("ssize-watch-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        let source = root.join("src/main.rs");
+        let history = root.join("history.csv");
+        std::fs::write(&source, "fn main() {}\n").unwrap();
+        std::fs::write(&history, "").unwrap();
+        let modified = |path: &Path, secs: u64| {
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            std::fs::File::options()
+                .write(true)
+                .open(path)
+                .and_then(|file| file.set_modified(time))
+                .unwrap();
+            time
+        };
+
+        let source_time = modified(&source, 1_000_000);
+        modified(&history, 2_000_000);
+        assert_eq!(
+            newest_change(&root, std::slice::from_ref(&history)),
+            source_time
+        );
+        assert!(newest_change(&root, &[]) > source_time);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}